@@ -1,3 +1,2 @@
 #!/bin/sh
-cargo build --release && target/release/rt > test.ppm
-
+cargo build --release && target/release/rt -o test.png
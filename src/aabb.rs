@@ -0,0 +1,65 @@
+use crate::vec3::Point3;
+use crate::Ray;
+
+/// Axis-aligned bounding box, stored as its two extreme corners.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Aabb {
+    pub fn new(min: Point3, max: Point3) -> Aabb {
+        Aabb { min, max }
+    }
+
+    /// Smallest box containing both `a` and `b`, whichever order the corners were given in.
+    pub fn from_points(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            min: Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn surrounding(self, other: Aabb) -> Aabb {
+        Aabb::from_points(
+            Point3::new(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            Point3::new(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        )
+    }
+
+    pub fn centroid(self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(self) -> f64 {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Slab test: does the ray pass through the box anywhere in `[t_min, t_max]`?
+    pub fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> bool {
+        for a in 0..3 {
+            let inv_d = 1.0 / r.dir[a];
+            let mut t0 = (self.min[a] - r.orig[a]) * inv_d;
+            let mut t1 = (self.max[a] - r.orig[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,207 @@
+//! Adaptive sampling: spending more samples on noisy pixels and fewer on flat ones.
+//!
+//! Every pixel starts with a few samples. Then, round by round, pixels whose estimated
+//! error is still above a threshold get more, noisiest first, until they've all
+//! converged or the image's sample budget (what fixed sampling would have spent) runs
+//! out.
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::vec3::{luminance, Color, COLOR_BLACK};
+
+/// Running totals of one pixel's samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelStats {
+    count: u32,
+    sum: Color,
+    // Welford's running mean and sum of squared deviations of the luminance
+    mean: f64,
+    m2: f64,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        PixelStats {
+            count: 0,
+            sum: COLOR_BLACK,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn add(&mut self, sample: Color) {
+        self.count += 1;
+        self.sum += sample;
+        let y = luminance(sample);
+        let delta = y - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (y - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Sum of all samples so far.
+    pub fn sum(&self) -> Color {
+        self.sum
+    }
+
+    /// Estimated error of the pixel's brightness as displayed: the standard error of the
+    /// mean luminance, scaled by the slope of its square root. That roughly follows how
+    /// the sRGB curve stretches dark values and squeezes bright ones, whatever exposure
+    /// and tone mapping do before it.
+    pub fn error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let variance = self.m2 / (self.count - 1) as f64;
+        let standard_error = (variance / self.count as f64).sqrt();
+        // d(sqrt(y)) = dy / (2 sqrt(y)); very dark pixels are capped so they can converge
+        standard_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+}
+
+/// How an adaptive render spends its samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Pixels stop once `PixelStats::error` is below this.
+    pub threshold: f64,
+    /// Samples every pixel gets before its error is trusted, and how many more each
+    /// round gives a noisy pixel.
+    pub batch: u32,
+    /// Most samples any one pixel can get.
+    pub max_samples: u32,
+}
+
+impl AdaptiveSampling {
+    /// Settings for the same total budget as `samples_per_pixel` samples everywhere.
+    pub fn new(threshold: f64, samples_per_pixel: u32) -> Self {
+        AdaptiveSampling {
+            threshold,
+            batch: (samples_per_pixel / 8).clamp(1, 16),
+            max_samples: samples_per_pixel.saturating_mul(8),
+        }
+    }
+
+    /// Renders `pixels` pixels with a budget of `samples_per_pixel` samples each on
+    /// average. `render` adds the given range of sample indices of a pixel to its stats,
+    /// and `progress` is told each round's number (from 1) and how many pixels it samples.
+    pub fn render<F, P>(
+        &self,
+        pixels: usize,
+        samples_per_pixel: u32,
+        render: F,
+        mut progress: P,
+    ) -> Vec<PixelStats>
+    where
+        F: Fn(usize, Range<u32>, &mut PixelStats) + Sync,
+        P: FnMut(u32, usize),
+    {
+        let mut stats = vec![PixelStats::default(); pixels];
+        let mut budget = pixels as u64 * samples_per_pixel as u64;
+
+        let mut noisy: Vec<usize> = (0..pixels).collect();
+        let mut round = 0;
+        while !noisy.is_empty() && budget > 0 {
+            // Noisiest first, so if the budget runs out mid-round it went where it helps.
+            noisy.sort_by(|&a, &b| stats[b].error().total_cmp(&stats[a].error()));
+            let affordable = (budget / self.batch as u64) as usize;
+            noisy.truncate(affordable.max(1));
+
+            round += 1;
+            progress(round, noisy.len());
+            let batch = self.batch;
+            let updates: Vec<(usize, PixelStats)> = noisy
+                .par_iter()
+                .map(|&pixel| {
+                    let mut pixel_stats = stats[pixel];
+                    let start = pixel_stats.count();
+                    render(pixel, start..start + batch, &mut pixel_stats);
+                    (pixel, pixel_stats)
+                })
+                .collect();
+            for (pixel, pixel_stats) in updates {
+                let spent = pixel_stats.count() - stats[pixel].count();
+                budget = budget.saturating_sub(spent as u64);
+                stats[pixel] = pixel_stats;
+            }
+
+            noisy.retain(|&pixel| {
+                let pixel_stats = &stats[pixel];
+                pixel_stats.error() > self.threshold && pixel_stats.count() < self.max_samples
+            });
+        }
+        stats
+    }
+}
+
+/// Colors a sample count for a heatmap: black for none, then through purple, red and
+/// yellow to white for `max`.
+pub fn heatmap_color(count: u32, max: u32) -> Color {
+    const RAMP: [[f64; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.4, 0.0, 0.6],
+        [0.9, 0.1, 0.1],
+        [1.0, 0.8, 0.0],
+        [1.0, 1.0, 1.0],
+    ];
+    let t = if max == 0 {
+        0.0
+    } else {
+        count as f64 / max as f64 * (RAMP.len() - 1) as f64
+    };
+    let i = (t as usize).min(RAMP.len() - 2);
+    let f = t - i as f64;
+    Color::from(RAMP[i]) * (1.0 - f) + Color::from(RAMP[i + 1]) * f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{random_double, seed_rng};
+
+    #[test]
+    fn test_pixel_stats() {
+        let mut stats = PixelStats::default();
+        assert_eq!(stats.error(), f64::INFINITY);
+        for y in [1.0, 3.0, 1.0, 3.0] {
+            stats.add(Color::new(y, y, y));
+        }
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.sum(), Color::new(8.0, 8.0, 8.0));
+        // sample variance 4/3, so the mean's standard error is sqrt(1/3)
+        let expected = (1.0f64 / 3.0).sqrt() / (2.0 * 2.0f64.sqrt());
+        assert!((stats.error() - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_adaptive_spends_samples_on_noisy_pixels() {
+        // pixel 0 is flat, pixel 1 is noisy
+        let adaptive = AdaptiveSampling::new(0.01, 64);
+        seed_rng(6);
+        let mut rounds = 0;
+        let stats = adaptive.render(
+            2,
+            64,
+            |pixel, range, stats| {
+                for _ in range {
+                    let y = if pixel == 0 { 0.5 } else { random_double() };
+                    stats.add(Color::new(y, y, y));
+                }
+            },
+            |round, _| rounds = round,
+        );
+        assert_eq!(stats[0].count(), adaptive.batch);
+        assert!(stats[1].count() > 64);
+        assert!(stats[0].count() + stats[1].count() <= 128);
+        // the first round samples both pixels, and each later one just the noisy one
+        assert_eq!(rounds, stats[1].count() / adaptive.batch);
+        assert_eq!(heatmap_color(0, 10), COLOR_BLACK);
+        assert_eq!(heatmap_color(10, 10), Color::new(1.0, 1.0, 1.0));
+    }
+}
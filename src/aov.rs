@@ -0,0 +1,393 @@
+//! Auxiliary output variables (AOVs): images of what each pixel's camera rays first hit,
+//! besides the light they bring back. Denoisers use the albedo and normals to tell
+//! texture and edges from noise; the rest help debug scenes.
+//!
+//! A pixel's AOVs come from the same camera rays as its first samples (up to
+//! `AOV_SAMPLES` of them), followed to their first hit. Normals, albedo, depth and
+//! position are averaged over those samples, depth and position over just the ones that
+//! hit something. IDs come from the first sample, since an average of IDs means nothing.
+//! Rays that escape have no normal, depth, position or IDs, and see the background as
+//! their albedo.
+//!
+//! Object IDs count from 1 in the order the objects were added to the scene, once the
+//! world is tagged with `HitList::with_object_ids`, and material IDs are the ones
+//! `MaterialIds` gave the materials when the scene was built (scene files number theirs
+//! in the order they're declared). 0 is nothing.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::film::Film;
+use crate::image_io::{rgb_channels, write_exr, write_image, ExrChannel, ImageFormat};
+use crate::integrator::{albedo, Scene};
+use crate::sampler::Sampler;
+use crate::tonemap::linear_to_srgb;
+use crate::util::stream_seed;
+use crate::vec3::{dot, Color, Point3, Vec3, COLOR_BLACK};
+use crate::{Ray, INF};
+
+/// Most samples per pixel the AOVs are gathered from; a few are enough for smooth edges.
+pub const AOV_SAMPLES: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    /// World-space normal of the surface, facing the camera.
+    Normal,
+    /// The surface's color: what it reflects, or what it emits if it's a light.
+    Albedo,
+    /// Distance to the surface along the camera's viewing direction.
+    Depth,
+    /// World-space position of the surface.
+    Position,
+    ObjectId,
+    MaterialId,
+}
+
+impl Aov {
+    /// Name of the AOV's layer in EXR files, and its suffix for separate images.
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+}
+
+/// Where to write an AOV as a separate image: next to the main image at `path`, with the
+/// AOV's name before the extension (e.g. `out.normal.png` for `out.png`).
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    match path.extension() {
+        Some(extension) => {
+            path.with_extension(format!("{}.{}", aov.name(), extension.to_string_lossy()))
+        }
+        None => path.with_extension(aov.name()),
+    }
+}
+
+/// Every AOV of one camera ray.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AovSample {
+    albedo: Color,
+    // `None` if the ray escaped
+    surface: Option<Surface>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Surface {
+    normal: Vec3,
+    depth: f64,
+    position: Point3,
+    object_id: u32,
+    material_id: u32,
+}
+
+impl AovSample {
+    /// Follows `ray` to its first hit. `forward` is the camera's viewing direction.
+    pub fn trace(ray: &Ray, forward: Vec3, scene: &Scene, sampler: &mut dyn Sampler) -> Self {
+        let rec = match scene.world.hit(ray, 0.001, INF) {
+            Some(rec) => rec,
+            None => {
+                return AovSample {
+                    albedo: scene.background.color(ray.dir),
+                    surface: None,
+                }
+            }
+        };
+        let surface = Surface {
+            normal: rec.normal,
+            depth: dot(rec.p - ray.orig, forward.unit_vector()),
+            position: rec.p,
+            object_id: rec.object_id,
+            material_id: rec.material_id,
+        };
+        AovSample {
+            albedo: albedo(ray, &rec, sampler),
+            surface: Some(surface),
+        }
+    }
+}
+
+/// The AOV images, filled in pixel by pixel.
+pub struct AovBuffers {
+    width: u32,
+    height: u32,
+    normal: Film,
+    albedo: Film,
+    depth: Film,
+    position: Film,
+    // from each pixel's first sample
+    object_ids: Vec<u32>,
+    material_ids: Vec<u32>,
+}
+
+impl AovBuffers {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        AovBuffers {
+            width,
+            height,
+            normal: Film::new(width, height),
+            albedo: Film::new(width, height),
+            depth: Film::new(width, height),
+            position: Film::new(width, height),
+            object_ids: vec![0; pixels],
+            material_ids: vec![0; pixels],
+        }
+    }
+
+    /// Gathers every pixel's AOVs, a row at a time. `trace` returns the samples of pixel
+    /// `(x, y)`, counting from the top-left.
+    pub fn render<F>(width: u32, height: u32, trace: F) -> Self
+    where
+        F: Fn(u32, u32) -> Vec<AovSample> + Sync,
+    {
+        let mut buffers = AovBuffers::new(width, height);
+        for y in 0..height {
+            let row: Vec<Vec<AovSample>> =
+                (0..width).into_par_iter().map(|x| trace(x, y)).collect();
+            for (x, samples) in row.iter().enumerate() {
+                buffers.add_pixel(x as u32, y, samples);
+            }
+        }
+        buffers
+    }
+
+    /// Adds all the samples of pixel `(x, y)`, counting from the top-left.
+    pub fn add_pixel(&mut self, x: u32, y: u32, samples: &[AovSample]) {
+        for sample in samples {
+            self.albedo.add_sample(x, y, sample.albedo);
+            if let Some(surface) = &sample.surface {
+                self.normal.add_sample(x, y, surface.normal);
+                let depth = surface.depth;
+                self.depth.add_sample(x, y, Color::new(depth, depth, depth));
+                self.position.add_sample(x, y, surface.position);
+            }
+        }
+        let i = y as usize * self.width as usize + x as usize;
+        let first = samples.first().and_then(|sample| sample.surface.as_ref());
+        self.object_ids[i] = first.map_or(0, |surface| surface.object_id);
+        self.material_ids[i] = first.map_or(0, |surface| surface.material_id);
+    }
+
+    /// The AOV's values, row by row from the top-left. One-channel AOVs (depth and the
+    /// IDs) repeat their value in every channel.
+    pub fn values(&self, aov: Aov) -> Vec<Color> {
+        let ids = |ids: Vec<u32>| {
+            let id = |id: u32| Color::new(id as f64, id as f64, id as f64);
+            ids.into_iter().map(id).collect()
+        };
+        match aov {
+            Aov::Normal => self.normal.radiance(),
+            Aov::Albedo => self.albedo.radiance(),
+            Aov::Depth => self.depth.radiance(),
+            Aov::Position => self.position.radiance(),
+            Aov::ObjectId => ids(self.object_ids.clone()),
+            Aov::MaterialId => ids(self.material_ids.clone()),
+        }
+    }
+
+    /// The AOV as display colors, for 8- and 16-bit images: normals mapped from `[-1, 1]`
+    /// to `[0, 1]`, depth from black up close to white at the farthest hit, position as
+    /// a grid repeating every unit, and a color per ID. Escaped rays are black.
+    pub fn display(&self, aov: Aov) -> Vec<Color> {
+        let values = self.values(aov);
+        let covered = |i: usize| {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            self.depth.count(x, y) > 0
+        };
+        let farthest = match aov {
+            Aov::Depth => values.iter().map(|depth| depth.x()).fold(0.0, f64::max),
+            _ => 0.0,
+        };
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| match aov {
+                Aov::Albedo => Color::new(
+                    linear_to_srgb(value.x().clamp(0.0, 1.0)),
+                    linear_to_srgb(value.y().clamp(0.0, 1.0)),
+                    linear_to_srgb(value.z().clamp(0.0, 1.0)),
+                ),
+                _ if !covered(i) => COLOR_BLACK,
+                Aov::Normal => (value + Color::new(1.0, 1.0, 1.0)) * 0.5,
+                Aov::Depth if farthest > 0.0 => value / farthest,
+                Aov::Depth => COLOR_BLACK,
+                Aov::Position => Color::new(
+                    value.x().rem_euclid(1.0),
+                    value.y().rem_euclid(1.0),
+                    value.z().rem_euclid(1.0),
+                ),
+                Aov::ObjectId | Aov::MaterialId => id_color(value.x() as u32),
+            })
+            .collect()
+    }
+
+    /// The AOV as a layer of an EXR image. IDs are always stored as 32-bit floats, so
+    /// they stay exact.
+    pub fn exr_channels(&self, aov: Aov, half: bool) -> Vec<ExrChannel> {
+        let values = self.values(aov);
+        let channel = |name: &str, value: fn(Color) -> f64, half: bool| {
+            let name = format!("{}.{name}", aov.name());
+            ExrChannel::new(name, values.iter().map(|&v| value(v)).collect(), half)
+        };
+        match aov {
+            Aov::Albedo => vec![
+                channel("R", Color::x, half),
+                channel("G", Color::y, half),
+                channel("B", Color::z, half),
+            ],
+            Aov::Normal | Aov::Position => vec![
+                channel("X", Color::x, half),
+                channel("Y", Color::y, half),
+                channel("Z", Color::z, half),
+            ],
+            Aov::Depth => vec![channel("Z", Color::x, half)],
+            Aov::ObjectId | Aov::MaterialId => vec![channel("id", Color::x, false)],
+        }
+    }
+
+    /// Writes `beauty` as an EXR image's RGB channels, with `aovs` as extra layers.
+    pub fn write_exr(
+        &self,
+        out: &mut impl Write,
+        beauty: &Film,
+        aovs: &[Aov],
+        half: bool,
+    ) -> io::Result<()> {
+        let mut channels = rgb_channels("", &beauty.radiance(), half);
+        for &aov in aovs {
+            channels.extend(self.exr_channels(aov, half));
+        }
+        write_exr(out, self.width, self.height, &channels)
+    }
+
+    /// Writes the AOV as an image of its own: its values for high dynamic range formats,
+    /// and display colors for the rest.
+    pub fn save(&self, aov: Aov, path: &Path, format: ImageFormat) -> io::Result<()> {
+        let pixels = if format.is_hdr() {
+            self.values(aov)
+        } else {
+            self.display(aov)
+        };
+        let mut out = BufWriter::new(File::create(path)?);
+        write_image(&mut out, format, self.width, self.height, &pixels)?;
+        out.flush()
+    }
+}
+
+// A bright color that's unlikely to be close to its neighbouring IDs', or black for 0.
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return COLOR_BLACK;
+    }
+    let bits = stream_seed(0, id as u64);
+    let channel = |shift: u32| 0.2 + 0.8 * ((bits >> shift) & 0xff) as f64 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::material::{DiffuseLight, Lambertian, MaterialIds};
+    use crate::sampler::IndependentSampler;
+    use crate::{Background, HitList, Sphere};
+
+    #[test]
+    fn test_first_hits() {
+        let mut material_ids = MaterialIds::default();
+        let light = material_ids.tag(Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))));
+        let red = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.8, 0.1, 0.1))));
+        let mut world = HitList::new();
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, -3.0),
+            1.0,
+            red.clone(),
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(3.0, 0.0, -3.0),
+            1.0,
+            light,
+        )));
+        world.add(Box::new(Sphere::new(
+            Point3::new(-3.0, 0.0, -3.0),
+            1.0,
+            red,
+        )));
+        let world = world.with_object_ids();
+        let background = Background::Solid(Color::new(0.0, 0.0, 0.5));
+        let scene = Scene {
+            world: &world,
+            lights: &HitList::new(),
+            background: &background,
+        };
+
+        // one pixel per sphere, from the left, then one of the sky
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let targets = [-3.0, 0.0, 3.0, 6.0];
+        let buffers = AovBuffers::render(4, 1, |x, _| {
+            let dir = Vec3::new(targets[x as usize], 0.0, -3.0);
+            let ray = Ray::new(Point3::new(0.0, 0.0, 0.0), dir);
+            vec![AovSample::trace(
+                &ray,
+                forward,
+                &scene,
+                &mut IndependentSampler,
+            )]
+        });
+
+        let normals = buffers.values(Aov::Normal);
+        assert!((normals[1] - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert_eq!(normals[3], COLOR_BLACK);
+        let depths: Vec<f64> = buffers.values(Aov::Depth).iter().map(|d| d.x()).collect();
+        assert!((depths[1] - 2.0).abs() < 1e-9, "{depths:?}");
+        assert!(depths[0] > 2.0 && depths[0] < 3.0);
+        assert_eq!(depths[3], 0.0);
+        let albedo = buffers.values(Aov::Albedo);
+        assert_eq!(albedo[1], Color::new(0.8, 0.1, 0.1));
+        assert_eq!(albedo[2], Color::new(4.0, 4.0, 4.0));
+        assert_eq!(albedo[3], Color::new(0.0, 0.0, 0.5));
+        let position = buffers.values(Aov::Position);
+        assert!((position[1] - Point3::new(0.0, 0.0, -2.0)).length() < 1e-9);
+
+        // objects in the order added; materials in the order tagged, shared by both reds
+        let ids = |aov| -> Vec<f64> { buffers.values(aov).iter().map(|id| id.x()).collect() };
+        assert_eq!(ids(Aov::ObjectId), vec![3.0, 1.0, 2.0, 0.0]);
+        assert_eq!(ids(Aov::MaterialId), vec![2.0, 2.0, 1.0, 0.0]);
+
+        let display = buffers.display(Aov::Depth);
+        assert_eq!(display[3], COLOR_BLACK);
+        assert!(display[0].x() > display[1].x());
+        assert_eq!(buffers.display(Aov::ObjectId)[0], id_color(3));
+
+        let channels = buffers.exr_channels(Aov::Normal, true);
+        let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["normal.X", "normal.Y", "normal.Z"]);
+        let channels = buffers.exr_channels(Aov::MaterialId, true);
+        assert_eq!(channels[0].name, "material_id.id");
+        assert!(!channels[0].half);
+    }
+
+    #[test]
+    fn test_aov_path() {
+        let path = Path::new("renders/out.png");
+        assert_eq!(
+            aov_path(path, Aov::Normal),
+            Path::new("renders/out.normal.png")
+        );
+        assert_eq!(
+            aov_path(Path::new("out"), Aov::Depth),
+            Path::new("out.depth")
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use std::cmp::Ordering;
+
+use crate::aabb::Aabb;
+use crate::{HitList, HitRecord, Hittable, Ray};
+
+// Leaves stop splitting at this many objects, unless the SAH says splitting is still cheaper.
+const MAX_LEAF_SIZE: usize = 4;
+// Cost of visiting a node, relative to intersecting a single object.
+const TRAVERSAL_COST: f64 = 0.125;
+
+/// Bounding volume hierarchy over the objects of a `HitList`.
+///
+/// Splits are chosen with the surface area heuristic. Objects without a bounding box
+/// (e.g. infinite planes) can't be placed in the tree, so they are kept in an unbounded
+/// leaf next to it and tested on every ray.
+pub struct BvhNode {
+    bbox: Option<Aabb>,
+    children: Children,
+}
+
+enum Children {
+    Leaf(Vec<Box<dyn Hittable>>),
+    Split(Box<BvhNode>, Box<BvhNode>),
+}
+
+struct Primitive {
+    bbox: Aabb,
+    centroid: [f64; 3],
+    object: Box<dyn Hittable>,
+}
+
+impl BvhNode {
+    pub fn new(list: HitList) -> BvhNode {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for object in list.objects {
+            match object.bounding_box() {
+                Some(bbox) => {
+                    let c = bbox.centroid();
+                    bounded.push(Primitive {
+                        bbox,
+                        centroid: [c.x(), c.y(), c.z()],
+                        object,
+                    })
+                }
+                None => unbounded.push(object),
+            }
+        }
+
+        match (bounded.is_empty(), unbounded.is_empty()) {
+            (true, _) => BvhNode::leaf(None, unbounded),
+            (false, true) => BvhNode::build(bounded),
+            (false, false) => BvhNode {
+                bbox: None,
+                children: Children::Split(
+                    Box::new(BvhNode::build(bounded)),
+                    Box::new(BvhNode::leaf(None, unbounded)),
+                ),
+            },
+        }
+    }
+
+    fn leaf(bbox: Option<Aabb>, objects: Vec<Box<dyn Hittable>>) -> BvhNode {
+        BvhNode {
+            bbox,
+            children: Children::Leaf(objects),
+        }
+    }
+
+    fn build(mut prims: Vec<Primitive>) -> BvhNode {
+        let bbox = prims
+            .iter()
+            .map(|p| p.bbox)
+            .reduce(Aabb::surrounding)
+            .expect("build is only called with objects");
+        let n = prims.len();
+        if n == 1 {
+            return BvhNode::leaf(Some(bbox), prims.into_iter().map(|p| p.object).collect());
+        }
+
+        let parent_area = bbox.surface_area();
+        let (axis, split, split_cost) = if parent_area > 0.0 {
+            best_sah_split(&mut prims)
+        } else {
+            // Everything sits at a single point, so the SAH can't tell splits apart.
+            (0, n / 2, f64::INFINITY)
+        };
+
+        let leaf_cost = n as f64 * parent_area;
+        if n <= MAX_LEAF_SIZE && leaf_cost <= TRAVERSAL_COST * parent_area + split_cost {
+            return BvhNode::leaf(Some(bbox), prims.into_iter().map(|p| p.object).collect());
+        }
+
+        prims.sort_by(|a, b| compare_centroids(a, b, axis));
+        let right = prims.split_off(split);
+        BvhNode {
+            bbox: Some(bbox),
+            children: Children::Split(
+                Box::new(BvhNode::build(prims)),
+                Box::new(BvhNode::build(right)),
+            ),
+        }
+    }
+}
+
+fn compare_centroids(a: &Primitive, b: &Primitive, axis: usize) -> Ordering {
+    a.centroid[axis].total_cmp(&b.centroid[axis])
+}
+
+/// Returns `(axis, split index, cost)` of the cheapest split, where the cost is the
+/// (un-normalized) SAH estimate `area(left) * n_left + area(right) * n_right`.
+fn best_sah_split(prims: &mut [Primitive]) -> (usize, usize, f64) {
+    let n = prims.len();
+    let mut best = (0, n / 2, f64::INFINITY);
+    let mut right_areas = vec![0.0; n];
+
+    for axis in 0..3 {
+        prims.sort_by(|a, b| compare_centroids(a, b, axis));
+
+        // Sweep from the right to find the area of every suffix...
+        let mut right = prims[n - 1].bbox;
+        for i in (1..n).rev() {
+            right = right.surrounding(prims[i].bbox);
+            right_areas[i] = right.surface_area();
+        }
+
+        // ...then from the left, splitting into [0, i) and [i, n).
+        let mut left = prims[0].bbox;
+        for i in 1..n {
+            left = left.surrounding(prims[i - 1].bbox);
+            let cost = left.surface_area() * i as f64 + right_areas[i] * (n - i) as f64;
+            if cost < best.2 {
+                best = (axis, i, cost);
+            }
+        }
+    }
+
+    best
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if let Some(bbox) = self.bbox {
+            if !bbox.hit(r, t_min, t_max) {
+                return None;
+            }
+        }
+
+        match &self.children {
+            Children::Leaf(objects) => {
+                let mut closest = None;
+                let mut closest_so_far = t_max;
+                for object in objects {
+                    if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                        closest_so_far = rec.t;
+                        closest = Some(rec);
+                    }
+                }
+                closest
+            }
+            Children::Split(left, right) => {
+                let hit_left = left.hit(r, t_min, t_max);
+                let closest_so_far = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+                right.hit(r, t_min, closest_so_far).or(hit_left)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::util::random_bounded;
+    use crate::vec3::{Color, Point3, Vec3};
+    use crate::Sphere;
+
+    #[test]
+    fn test_bvh_matches_linear_list() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let spheres: Vec<(Point3, f64)> = (0..200)
+            .map(|_| {
+                (
+                    Vec3::new_random_bounded(-10.0, 10.0),
+                    random_bounded(0.1, 1.5),
+                )
+            })
+            .collect();
+
+        let mut list = HitList::new();
+        let mut bvh_list = HitList::new();
+        for &(center, radius) in &spheres {
+            list.add(Box::new(Sphere::new(center, radius, Arc::clone(&mat))));
+            bvh_list.add(Box::new(Sphere::new(center, radius, Arc::clone(&mat))));
+        }
+        let bvh = BvhNode::new(bvh_list);
+
+        for _ in 0..2000 {
+            let ray = Ray::new(
+                Vec3::new_random_bounded(-15.0, 15.0),
+                Vec3::new_random_unit_vector(),
+            );
+            let expected = list.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t);
+            let actual = bvh.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -0,0 +1,632 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::{Parser, ValueEnum};
+use rt::aov::Aov;
+use rt::image_io::ImageFormat;
+use rt::integrator::{
+    AmbientOcclusionIntegrator, DebugIntegrator, DebugView, DirectLightingIntegrator, Integrator,
+    PathIntegrator,
+};
+use rt::sampler::SamplerKind;
+use rt::scene_file::{RenderSettings, SceneFile};
+use rt::sky::PhysicalSky;
+use rt::tonemap::{Dither, ToneMap};
+use rt::vec3::{Color, Vec3};
+use rt::Background;
+
+/// Ray traces a scene and writes the image.
+///
+/// Settings come from the scene (a built-in one, or a scene file), and any flags given
+/// here override them.
+#[derive(Parser, Debug)]
+#[command(name = "rt", version)]
+pub struct Args {
+    /// Scene to render: `simple`, `random`, `light`, `cornell`, `cornell_smoke`, or the path to a TOML scene file
+    #[arg(long, value_name = "NAME|PATH")]
+    pub scene: Option<String>,
+
+    /// Image width in pixels. Without --height, the scene's aspect ratio is kept
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+
+    /// Image height in pixels. Without --width, the scene's aspect ratio is kept
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+
+    /// Rays traced per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+
+    /// Maximum number of bounces per ray
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    pub max_depth: Option<i32>,
+
+    /// How to pick the random numbers for each pixel's samples
+    #[arg(long, value_enum)]
+    pub sampler: Option<SamplerChoice>,
+
+    /// Sample adaptively: stop sampling pixels once their estimated error is below this
+    /// (e.g. 0.005), and spend what's saved on noisier ones. --samples becomes the
+    /// average
+    #[arg(long, value_name = "THRESHOLD", value_parser = parse_positive)]
+    pub adaptive: Option<f64>,
+
+    /// Also write a heatmap of how many samples each pixel took to this path
+    #[arg(long, value_name = "PATH")]
+    pub heatmap: Option<PathBuf>,
+
+    /// Bounces after which paths may end early at random (Russian roulette)
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..))]
+    pub roulette_depth: Option<i32>,
+
+    /// Where to write the image [default: stdout]
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Image format [default: from the output's extension, or ppm-ascii on stdout]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Brighten (or darken, if negative) the image by this many stops. HDR formats are
+    /// written without it
+    #[arg(long, value_name = "EV", allow_hyphen_values = true)]
+    pub exposure: Option<f64>,
+
+    /// How to bring radiance into the displayable range
+    #[arg(long, value_enum)]
+    pub tone_map: Option<ToneMapChoice>,
+
+    /// Luminance that becomes white with `--tone-map extended-reinhard`
+    #[arg(long, value_parser = parse_positive)]
+    pub white_point: Option<f64>,
+
+    /// Dither 8- and 16-bit images to hide banding
+    #[arg(long, value_enum)]
+    pub dither: Option<DitherChoice>,
+
+    /// Also write these auxiliary images (e.g. `normal,albedo` for a denoiser): as layers
+    /// of an EXR image, or else next to the output, like `out.normal.png`
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "AOV,...")]
+    pub aov: Vec<AovChoice>,
+
+    /// How to compute each pixel's color
+    #[arg(long, value_enum, default_value_t = IntegratorKind::Path)]
+    pub integrator: IntegratorKind,
+
+    /// How far away objects still occlude a point, for `--integrator ao` [default: any
+    /// distance]
+    #[arg(long, value_parser = parse_positive)]
+    pub ao_distance: Option<f64>,
+
+    /// Camera position, as `x,y,z`
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookfrom: Option<Vec3>,
+
+    /// Point the camera looks at, as `x,y,z`
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub lookat: Option<Vec3>,
+
+    /// Camera "up" direction, as `x,y,z`
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    pub vup: Option<Vec3>,
+
+    /// Vertical field of view, in degrees
+    #[arg(long, value_parser = parse_vfov)]
+    pub vfov: Option<f64>,
+
+    /// Lens aperture (0 for a pinhole camera with no defocus blur)
+    #[arg(long, value_parser = parse_non_negative)]
+    pub aperture: Option<f64>,
+
+    /// Distance to the plane of perfect focus
+    #[arg(long, value_parser = parse_positive)]
+    pub focus_dist: Option<f64>,
+
+    /// Shutter open and close times, as `open,close`; moving objects blur in between
+    #[arg(long, value_name = "OPEN,CLOSE", value_parser = parse_shutter, allow_hyphen_values = true)]
+    pub shutter: Option<(f64, f64)>,
+
+    /// Replace the scene's background with a solid color, as `r,g,b` (e.g. `0,0,0`)
+    #[arg(long, value_name = "R,G,B", value_parser = parse_vec3)]
+    pub background: Option<Color>,
+
+    /// Replace the scene's background with a daylight sky, with the sun in direction
+    /// `x,y,z` (e.g. `1,1,0` for a sun 45 degrees up)
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_direction, allow_hyphen_values = true)]
+    pub sky: Option<Vec3>,
+
+    /// Haziness of the `--sky`, from about 2 (very clear) to 10 (hazy)
+    #[arg(long, default_value_t = 3.0, value_parser = parse_positive)]
+    pub turbidity: f64,
+
+    /// Light the scene with an equirectangular environment map (`.hdr`, `.pfm`, `.png` or
+    /// `.ppm`) instead of its background
+    #[arg(long, value_name = "PATH")]
+    pub environment: Option<PathBuf>,
+
+    /// Turn the environment map by this many degrees about the vertical axis
+    #[arg(
+        long,
+        value_name = "DEGREES",
+        default_value_t = 0.0,
+        allow_hyphen_values = true
+    )]
+    pub environment_rotation: f64,
+
+    /// Scale the environment map's brightness
+    #[arg(long, value_name = "SCALE", default_value_t = 1.0, value_parser = parse_positive)]
+    pub environment_intensity: f64,
+
+    /// Seed for all random numbers; the same seed and settings give the same image
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Number of render threads [default: one per core]
+    #[arg(short = 'j', long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// ASCII (P3) PPM
+    PpmAscii,
+    /// Binary (P6) PPM
+    Ppm,
+    /// 8-bit PNG
+    Png,
+    /// 16-bit PNG
+    Png16,
+    /// OpenEXR with half floats, keeping the full range of radiance
+    Exr,
+    /// OpenEXR with 32-bit floats
+    ExrFloat,
+    /// Radiance RGBE (.hdr)
+    Hdr,
+    /// Portable float map
+    Pfm,
+}
+
+impl From<Format> for ImageFormat {
+    fn from(format: Format) -> ImageFormat {
+        match format {
+            Format::PpmAscii => ImageFormat::PpmAscii,
+            Format::Ppm => ImageFormat::Ppm,
+            Format::Png => ImageFormat::Png,
+            Format::Png16 => ImageFormat::Png16,
+            Format::Exr => ImageFormat::Exr,
+            Format::ExrFloat => ImageFormat::ExrFloat,
+            Format::Hdr => ImageFormat::Hdr,
+            Format::Pfm => ImageFormat::Pfm,
+        }
+    }
+}
+
+// Mirrors `SamplerKind`, which can't derive `ValueEnum` as the library doesn't use clap.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SamplerChoice {
+    /// Independent random numbers
+    Independent,
+    /// Jittered strata (correlated multi-jittered in 2D)
+    Stratified,
+    /// Randomly shifted Halton sequence
+    Halton,
+    /// Owen-scrambled Sobol sequence
+    Sobol,
+}
+
+impl From<SamplerChoice> for SamplerKind {
+    fn from(choice: SamplerChoice) -> SamplerKind {
+        match choice {
+            SamplerChoice::Independent => SamplerKind::Independent,
+            SamplerChoice::Stratified => SamplerKind::Stratified,
+            SamplerChoice::Halton => SamplerKind::Halton,
+            SamplerChoice::Sobol => SamplerKind::Sobol,
+        }
+    }
+}
+
+// Mirrors `ToneMap`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ToneMapChoice {
+    /// Clip everything brighter than white
+    Clamp,
+    /// Reinhard's operator, which never quite reaches white
+    Reinhard,
+    /// Reinhard's operator, reaching white at --white-point
+    ExtendedReinhard,
+    /// The ACES filmic curve
+    Aces,
+    /// AgX, which desaturates bright colors like film
+    Agx,
+}
+
+impl From<ToneMapChoice> for ToneMap {
+    fn from(choice: ToneMapChoice) -> ToneMap {
+        match choice {
+            ToneMapChoice::Clamp => ToneMap::Clamp,
+            ToneMapChoice::Reinhard => ToneMap::Reinhard,
+            ToneMapChoice::ExtendedReinhard => ToneMap::ExtendedReinhard,
+            ToneMapChoice::Aces => ToneMap::Aces,
+            ToneMapChoice::Agx => ToneMap::Agx,
+        }
+    }
+}
+
+// Mirrors `Dither`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum DitherChoice {
+    /// No dithering
+    None,
+    /// An 8x8 Bayer matrix
+    Ordered,
+    /// A blue-noise mask, which leaves no visible pattern
+    BlueNoise,
+}
+
+impl From<DitherChoice> for Dither {
+    fn from(choice: DitherChoice) -> Dither {
+        match choice {
+            DitherChoice::None => Dither::None,
+            DitherChoice::Ordered => Dither::Ordered,
+            DitherChoice::BlueNoise => Dither::BlueNoise,
+        }
+    }
+}
+
+// Mirrors `Aov`.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum AovChoice {
+    /// World-space surface normals
+    Normal,
+    /// Surface colors, without lighting
+    Albedo,
+    /// Distance along the camera's viewing direction
+    Depth,
+    /// World-space positions
+    Position,
+    /// A number per object
+    ObjectId,
+    /// A number per material
+    MaterialId,
+}
+
+impl From<AovChoice> for Aov {
+    fn from(choice: AovChoice) -> Aov {
+        match choice {
+            AovChoice::Normal => Aov::Normal,
+            AovChoice::Albedo => Aov::Albedo,
+            AovChoice::Depth => Aov::Depth,
+            AovChoice::Position => Aov::Position,
+            AovChoice::ObjectId => Aov::ObjectId,
+            AovChoice::MaterialId => Aov::MaterialId,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum IntegratorKind {
+    /// Full global illumination
+    Path,
+    /// Only light arriving straight from light sources and the background
+    Direct,
+    /// Ambient occlusion: how much of the sky each point can see
+    Ao,
+    /// Debug view: surface normals
+    Normal,
+    /// Debug view: distance from the camera
+    Depth,
+    /// Debug view: surface colors, without lighting
+    Albedo,
+}
+
+impl Args {
+    /// The integrator picked with --integrator, set up from `settings`.
+    pub fn integrator(&self, settings: &RenderSettings) -> Box<dyn Integrator> {
+        let max_depth = settings.max_depth;
+        let debug = |view| -> Box<dyn Integrator> { Box::new(DebugIntegrator { view }) };
+        match self.integrator {
+            IntegratorKind::Path => Box::new(PathIntegrator {
+                max_depth,
+                roulette_depth: settings.roulette_depth,
+            }),
+            IntegratorKind::Direct => Box::new(DirectLightingIntegrator { max_depth }),
+            IntegratorKind::Ao => Box::new(AmbientOcclusionIntegrator {
+                distance: self.ao_distance.unwrap_or(f64::INFINITY),
+            }),
+            IntegratorKind::Normal => debug(DebugView::Normal),
+            IntegratorKind::Depth => debug(DebugView::Depth),
+            IntegratorKind::Albedo => debug(DebugView::Albedo),
+        }
+    }
+
+    /// Format to write the image in, falling back to the output's file extension.
+    pub fn image_format(&self) -> Result<ImageFormat, String> {
+        match (self.format, &self.output) {
+            (Some(format), _) => Ok(format.into()),
+            (None, None) => Ok(ImageFormat::PpmAscii),
+            (None, Some(path)) => ImageFormat::from_path(path).ok_or_else(|| {
+                format!(
+                    "can't tell the image format of `{}` from its extension, use --format",
+                    path.display()
+                )
+            }),
+        }
+    }
+
+    /// Overrides scene settings with whatever was given on the command line.
+    pub fn apply(&self, scene: &mut SceneFile) {
+        let (settings, camera) = (&mut scene.render, &mut scene.camera);
+        let aspect_ratio = settings.aspect_ratio();
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => {
+                settings.image_width = width;
+                settings.image_height = height;
+            }
+            (Some(width), None) => {
+                settings.image_width = width;
+                settings.image_height = ((width as f64 / aspect_ratio) as u32).max(1);
+            }
+            (None, Some(height)) => {
+                settings.image_width = ((height as f64 * aspect_ratio) as u32).max(1);
+                settings.image_height = height;
+            }
+            (None, None) => {}
+        }
+        if let Some(samples) = self.samples {
+            settings.samples_per_pixel = samples;
+        }
+        if let Some(max_depth) = self.max_depth {
+            settings.max_depth = max_depth;
+        }
+        if let Some(threshold) = self.adaptive {
+            settings.adaptive_threshold = Some(threshold);
+        }
+        if let Some(sampler) = self.sampler {
+            settings.sampler = sampler.into();
+        }
+        if let Some(roulette_depth) = self.roulette_depth {
+            settings.roulette_depth = roulette_depth;
+        }
+        if let Some(seed) = self.seed {
+            settings.seed = seed;
+        }
+        if let Some(exposure) = self.exposure {
+            settings.tone_mapping.exposure = exposure;
+        }
+        if let Some(tone_map) = self.tone_map {
+            settings.tone_mapping.operator = tone_map.into();
+        }
+        if let Some(white_point) = self.white_point {
+            settings.tone_mapping.white_point = white_point;
+        }
+        if let Some(dither) = self.dither {
+            settings.tone_mapping.dither = dither.into();
+        }
+        if !self.aov.is_empty() {
+            settings.aovs = self.aov.iter().map(|&aov| aov.into()).collect();
+        }
+
+        if let Some(lookfrom) = self.lookfrom {
+            camera.lookfrom = lookfrom;
+        }
+        if let Some(lookat) = self.lookat {
+            camera.lookat = lookat;
+        }
+        if let Some(vup) = self.vup {
+            camera.vup = vup;
+        }
+        if let Some(vfov) = self.vfov {
+            camera.vfov = vfov;
+        }
+        if let Some(aperture) = self.aperture {
+            camera.aperture = aperture;
+        }
+        if let Some(focus_dist) = self.focus_dist {
+            camera.focus_dist = focus_dist;
+        }
+        if let Some((open, close)) = self.shutter {
+            camera.shutter_open = open;
+            camera.shutter_close = close;
+        }
+
+        if let Some(color) = self.background {
+            scene.background = Background::Solid(color);
+        }
+        if let Some(sun_direction) = self.sky {
+            let ground_albedo = Color::new(0.3, 0.3, 0.3);
+            let sky = PhysicalSky::new(sun_direction, self.turbidity, ground_albedo, 1.0);
+            scene.background = Background::Sky(Arc::new(sky));
+        }
+    }
+
+    /// Name of the scene to render, honouring the deprecated `RANDOM_SCENE` variable.
+    pub fn scene(&self) -> String {
+        match &self.scene {
+            Some(scene) => scene.clone(),
+            None if env::var("RANDOM_SCENE").is_ok() => {
+                eprintln!("warning: RANDOM_SCENE is deprecated, use `--scene random`");
+                "random".to_string()
+            }
+            None => "simple".to_string(),
+        }
+    }
+}
+
+/// Applies the quality presets from the old `FAST_MODE`/`DETAIL_MODE` environment
+/// variables. Call before `Args::apply` so explicit flags still win.
+pub fn apply_deprecated_env(settings: &mut RenderSettings) {
+    if env::var("FAST_MODE").is_ok() {
+        eprintln!("warning: FAST_MODE is deprecated, use `--samples 40 --max-depth 3`");
+        settings.max_depth = 3;
+        settings.samples_per_pixel = 40;
+    }
+    if env::var("DETAIL_MODE").is_ok() {
+        eprintln!("warning: DETAIL_MODE is deprecated, use `--samples 500 --max-depth 50`");
+        settings.max_depth = 50;
+        settings.samples_per_pixel = 500;
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<Vec3, String> {
+    let parts = s
+        .split(',')
+        .map(|part| part.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("{err} (expected `x,y,z`)"))?;
+    match parts[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected 3 numbers `x,y,z`, got {}", parts.len())),
+    }
+}
+
+fn parse_direction(s: &str) -> Result<Vec3, String> {
+    match parse_vec3(s)? {
+        dir if dir.near_zero() => Err("direction must not be zero".to_string()),
+        dir => Ok(dir),
+    }
+}
+
+fn parse_shutter(s: &str) -> Result<(f64, f64), String> {
+    let (open, close) = s
+        .split_once(',')
+        .ok_or_else(|| "expected `open,close`".to_string())?;
+    let (open, close) = (parse_f64(open.trim())?, parse_f64(close.trim())?);
+    if close < open {
+        return Err(format!(
+            "the shutter closes ({close}) before it opens ({open})"
+        ));
+    }
+    Ok((open, close))
+}
+
+fn parse_f64(s: &str) -> Result<f64, String> {
+    s.parse::<f64>().map_err(|err| err.to_string())
+}
+
+fn parse_vfov(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        vfov if vfov > 0.0 && vfov < 180.0 => Ok(vfov),
+        vfov => Err(format!("must be between 0 and 180 degrees, got {vfov}")),
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if x >= 0.0 => Ok(x),
+        x => Err(format!("must not be negative, got {x}")),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match parse_f64(s)? {
+        x if x > 0.0 => Ok(x),
+        x => Err(format!("must be positive, got {x}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use rt::scene_file::parse_scene;
+
+    fn args(flags: &[&str]) -> Result<Args, clap::Error> {
+        Args::try_parse_from(std::iter::once("rt").chain(flags.iter().copied()))
+    }
+
+    #[test]
+    fn test_parse_vec3() {
+        assert_eq!(parse_vec3("1,2.5,-3"), Ok(Vec3::new(1.0, 2.5, -3.0)));
+        assert_eq!(parse_vec3(" -1, -2 ,0 "), Ok(Vec3::new(-1.0, -2.0, 0.0)));
+        assert!(parse_vec3("1,2").is_err());
+        assert!(parse_vec3("1,2,3,4").is_err());
+        assert!(parse_vec3("1,x,3").is_err());
+        assert!(parse_direction("0,0,0").is_err());
+        assert_eq!(parse_shutter("0,1"), Ok((0.0, 1.0)));
+        assert!(parse_shutter("1,0").is_err());
+        assert!(parse_vfov("180").is_err());
+        assert!(parse_non_negative("-0.5").is_err());
+        assert!(parse_positive("0").is_err());
+
+        // negative components aren't mistaken for flags
+        let parsed = args(&["--lookfrom", "-1,2,-3"]).unwrap();
+        assert_eq!(parsed.lookfrom, Some(Vec3::new(-1.0, 2.0, -3.0)));
+    }
+
+    #[test]
+    fn test_rejects_invalid_flags() {
+        for flags in [
+            &["--width", "0"][..],
+            &["--samples", "0"],
+            &["--max-depth", "0"],
+            &["--vfov", "0"],
+            &["--aperture", "-1"],
+            &["--shutter", "1,0"],
+            &["--sky", "0,0,0"],
+            &["--format", "gif"],
+            &["--white-point", "0"],
+        ] {
+            assert!(args(flags).is_err(), "{flags:?}");
+        }
+    }
+
+    #[test]
+    fn test_apply_overrides_the_scene() {
+        let source = "[render]\nwidth = 300\nheight = 200\nsamples_per_pixel = 10\n\
+                      [camera]\nvfov = 40\n";
+        let mut scene = parse_scene(source, Path::new("test.toml")).unwrap();
+        let parsed = args(&[
+            "--width",
+            "600",
+            "--samples",
+            "7",
+            "--vfov",
+            "30",
+            "--background",
+            "0,0,0",
+            "--tone-map",
+            "aces",
+        ])
+        .unwrap();
+        parsed.apply(&mut scene);
+        // the scene's aspect ratio is kept
+        assert_eq!(
+            (scene.render.image_width, scene.render.image_height),
+            (600, 400)
+        );
+        assert_eq!(scene.render.samples_per_pixel, 7);
+        assert_eq!(scene.render.max_depth, 50);
+        assert_eq!(scene.render.tone_mapping.operator, ToneMap::Aces);
+        assert_eq!(scene.camera.vfov, 30.0);
+        assert_eq!(
+            scene.background,
+            Background::Solid(Color::new(0.0, 0.0, 0.0))
+        );
+
+        // without flags, nothing changes
+        let mut unchanged = parse_scene(source, Path::new("test.toml")).unwrap();
+        args(&[]).unwrap().apply(&mut unchanged);
+        assert_eq!(unchanged.render.image_width, 300);
+        assert_eq!(unchanged.render.samples_per_pixel, 10);
+        assert_eq!(unchanged.camera.vfov, 40.0);
+    }
+
+    #[test]
+    fn test_image_format() {
+        let format = |flags: &[&str]| args(flags).unwrap().image_format();
+        assert_eq!(format(&[]), Ok(ImageFormat::PpmAscii));
+        assert_eq!(format(&["-o", "out.png"]), Ok(ImageFormat::Png));
+        assert_eq!(format(&["-o", "out.exr"]), Ok(ImageFormat::Exr));
+        // --format wins over the extension
+        assert_eq!(
+            format(&["-o", "out.png", "--format", "png16"]),
+            Ok(ImageFormat::Png16)
+        );
+        assert_eq!(format(&["--format", "ppm"]), Ok(ImageFormat::Ppm));
+        assert!(format(&["-o", "out.gif"]).is_err());
+        assert!(format(&["-o", "out"]).is_err());
+    }
+}
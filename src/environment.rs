@@ -0,0 +1,189 @@
+//! Lighting a scene with a photographed (or rendered) panorama of its surroundings.
+//!
+//! An environment map is an equirectangular (latitude-longitude) image: `x` goes once
+//! around the horizon and `y` from straight up at the top to straight down at the
+//! bottom. The middle of the image is in the `-z` direction, where the default camera
+//! looks.
+//!
+//! Outdoor maps usually get most of their light from a small, very bright sun, which
+//! scattered rays rarely find. So the map also keeps a distribution over its pixels in
+//! proportion to their brightness, for picking directions towards the bright parts.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::image_io::{load_image, Image};
+use crate::sampling::Distribution2D;
+use crate::texture::srgb_to_linear;
+use crate::vec3::{luminance, Color, Vec3};
+
+#[derive(Clone, PartialEq)]
+pub struct EnvironmentMap {
+    image: Image,
+    // about the y axis, in radians
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Turns the map `rotation_degrees` counterclockwise (seen from above) and scales its
+    /// brightness by `intensity`. Unless `image` is `linear`, it's taken as sRGB-encoded
+    /// and linearized.
+    pub fn new(mut image: Image, rotation_degrees: f64, intensity: f64) -> Self {
+        if !image.linear {
+            for pixel in image.pixels.iter_mut() {
+                *pixel = Color::new(
+                    srgb_to_linear(pixel.x()),
+                    srgb_to_linear(pixel.y()),
+                    srgb_to_linear(pixel.z()),
+                );
+            }
+            image.linear = true;
+        }
+
+        // Each pixel covers less of the sphere the closer its row is to a pole.
+        let (width, height) = (image.width as usize, image.height as usize);
+        let weights: Vec<f64> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, &pixel)| {
+                let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+                luminance(pixel).max(0.0) * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, width);
+        EnvironmentMap {
+            image,
+            rotation: rotation_degrees.to_radians(),
+            intensity,
+            distribution,
+        }
+    }
+
+    /// Loads an equirectangular image; `.hdr` or `.pfm` for real lighting, though PNG and
+    /// PPM work too.
+    pub fn load(path: &Path, rotation_degrees: f64, intensity: f64) -> io::Result<Self> {
+        let image = load_image(path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "environment map is empty",
+            ));
+        }
+        Ok(Self::new(image, rotation_degrees, intensity))
+    }
+
+    // Image coordinates in [0, 1)² of a direction.
+    fn to_uv(&self, dir: Vec3) -> (f64, f64) {
+        let dir = dir.unit_vector();
+        let phi = dir.x().atan2(-dir.z()) - self.rotation;
+        let u = (0.5 + phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = dir.y().clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// Light arriving from direction `dir`.
+    pub fn color(&self, dir: Vec3) -> Color {
+        let (u, v) = self.to_uv(dir);
+        let x = ((u * self.image.width as f64) as u32).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as u32).min(self.image.height - 1);
+        self.image.pixel(x, y) * self.intensity
+    }
+
+    /// Picks a direction, mostly towards the bright parts of the map, from a sample in
+    /// `[0, 1)²`.
+    pub fn sample(&self, sample: (f64, f64)) -> Vec3 {
+        let ((u, v), _) = self.distribution.sample(sample);
+        let theta = v * PI;
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    /// Density (per unit solid angle) of `sample` picking `dir`.
+    pub fn pdf(&self, dir: Vec3) -> f64 {
+        let (u, v) = self.to_uv(dir);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // the image covers 2π by π radians
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+impl fmt::Debug for EnvironmentMap {
+    // The pixels would drown out everything else.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMap")
+            .field("width", &self.image.width)
+            .field("height", &self.image.height)
+            .field("rotation", &self.rotation.to_degrees())
+            .field("intensity", &self.intensity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{random_2d, seed_rng};
+
+    // a dim sky with one bright pixel
+    fn sunny_map() -> EnvironmentMap {
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Color::new(0.5, 0.5, 0.5); width * height];
+        pixels[2 * width + 5] = Color::new(1000.0, 1000.0, 1000.0);
+        let image = Image {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+            linear: true,
+        };
+        EnvironmentMap::new(image, 30.0, 2.0)
+    }
+
+    #[test]
+    fn test_directions_round_trip() {
+        let map = sunny_map();
+        assert_eq!(
+            map.color(Vec3::new(0.0, 1.0, 0.0)),
+            Color::new(1.0, 1.0, 1.0)
+        );
+        // the sun has almost all the weight, so most samples land in it
+        let sun = map.sample((0.5, 0.5));
+        assert_eq!(map.color(sun), Color::new(2000.0, 2000.0, 2000.0));
+        assert!((map.to_uv(sun).0 - 5.5 / 16.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_importance_sampling() {
+        let map = sunny_map();
+        seed_rng(4);
+        let n = 20_000;
+        let mut sunny = 0;
+        let mut total = 0.0;
+        for _ in 0..n {
+            let dir = map.sample(random_2d());
+            assert!((dir.length() - 1.0).abs() < 1e-9);
+            if map.color(dir).x() > 1.0 {
+                sunny += 1;
+            }
+            // averaging 1 / pdf estimates the sphere's solid angle
+            total += 1.0 / map.pdf(dir);
+        }
+        assert!(sunny > n * 9 / 10, "{sunny}");
+        let solid_angle = total / n as f64;
+        assert!(
+            (solid_angle - 4.0 * PI).abs() < 0.05 * 4.0 * PI,
+            "{solid_angle}"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! The image being rendered: every pixel's samples, summed in linear radiance.
+//!
+//! Nothing is tone mapped until the film is written, and then only for formats that need
+//! display-ready colors; the high dynamic range ones get the averaged radiance as is
+//! (without even the exposure), for compositing or denoising elsewhere.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::image_io::{write_image, ImageFormat};
+use crate::tonemap::ToneMapping;
+use crate::vec3::{Color, COLOR_BLACK};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    // per pixel, row by row from the top-left
+    sums: Vec<Color>,
+    counts: Vec<u32>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = width as usize * height as usize;
+        Film {
+            width,
+            height,
+            sums: vec![COLOR_BLACK; pixels],
+            counts: vec![0; pixels],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height);
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// Adds one sample of pixel `(x, y)`, counting from the top-left.
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        self.add_samples(x, y, color, 1);
+    }
+
+    /// Adds `count` samples of pixel `(x, y)` that sum to `sum`.
+    pub fn add_samples(&mut self, x: u32, y: u32, sum: Color, count: u32) {
+        let i = self.index(x, y);
+        self.sums[i] += sum;
+        self.counts[i] += count;
+    }
+
+    /// Number of samples pixel `(x, y)` has.
+    pub fn count(&self, x: u32, y: u32) -> u32 {
+        self.counts[self.index(x, y)]
+    }
+
+    /// Average radiance of pixel `(x, y)`; black if it has no samples.
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        let i = self.index(x, y);
+        self.average(i)
+    }
+
+    fn average(&self, i: usize) -> Color {
+        match self.counts[i] {
+            0 => COLOR_BLACK,
+            count => self.sums[i] / count as f64,
+        }
+    }
+
+    /// Every pixel's average radiance, row by row from the top-left.
+    pub fn radiance(&self) -> Vec<Color> {
+        (0..self.sums.len()).map(|i| self.average(i)).collect()
+    }
+
+    /// Every pixel as a display-ready color, row by row from the top-left.
+    pub fn display(&self, tone_mapping: &ToneMapping) -> Vec<Color> {
+        self.radiance()
+            .into_iter()
+            .map(|color| tone_mapping.apply(color))
+            .collect()
+    }
+
+    /// Writes the image in `format`: radiance for high dynamic range formats, and display
+    /// colors, dithered if `tone_mapping` says so, for the rest.
+    pub fn write(
+        &self,
+        out: &mut impl Write,
+        format: ImageFormat,
+        tone_mapping: &ToneMapping,
+    ) -> io::Result<()> {
+        let pixels = match format.quantization_step() {
+            None => self.radiance(),
+            Some(step) => {
+                let mut pixels = self.display(tone_mapping);
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+                    *pixel = tone_mapping.dither(x, y, *pixel, step);
+                }
+                pixels
+            }
+        };
+        write_image(out, format, self.width, self.height, &pixels)
+    }
+
+    pub fn save(
+        &self,
+        path: &Path,
+        format: ImageFormat,
+        tone_mapping: &ToneMapping,
+    ) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out, format, tone_mapping)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::linear_to_srgb;
+
+    #[test]
+    fn test_film_keeps_radiance() {
+        let mut film = Film::new(2, 1);
+        film.add_sample(0, 0, Color::new(4.0, 0.0, 1.0));
+        film.add_sample(0, 0, Color::new(12.0, 0.0, 0.0));
+        film.add_samples(1, 0, Color::new(0.75, 0.75, 0.75), 3);
+        assert_eq!(film.pixel(0, 0), Color::new(8.0, 0.0, 0.5));
+        let tone_mapping = ToneMapping::default();
+        let gray = linear_to_srgb(0.25);
+        assert_eq!(film.display(&tone_mapping)[1], Color::new(gray, gray, gray));
+
+        let mut pfm = Vec::new();
+        film.write(&mut pfm, ImageFormat::Pfm, &tone_mapping)
+            .unwrap();
+        let floats: Vec<f32> = pfm[pfm.len() - 24..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats, vec![8.0, 0.0, 0.5, 0.25, 0.25, 0.25]);
+
+        let mut ppm = Vec::new();
+        film.write(&mut ppm, ImageFormat::Ppm, &tone_mapping)
+            .unwrap();
+        assert!(ppm.ends_with(&[255, 0, 188, 137, 137, 137]));
+    }
+}
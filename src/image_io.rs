@@ -0,0 +1,939 @@
+//! Reading and writing images on disk.
+//!
+//! Pixels are row by row from the top-left. For the low dynamic range formats they're
+//! display-ready colors (already averaged and gamma-corrected) with channels in `[0, 1]`,
+//! and are clamped and quantized when writing. The high dynamic range ones (see
+//! `ImageFormat::is_hdr`) take linear radiance and store it as is.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::vec3::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// ASCII (`P3`) PPM
+    PpmAscii,
+    /// Binary (`P6`) PPM, 8 bits per channel
+    Ppm,
+    /// PNG, 8 bits per channel
+    Png,
+    /// PNG, 16 bits per channel
+    Png16,
+    /// OpenEXR, 16-bit (half) floats, uncompressed
+    Exr,
+    /// OpenEXR, 32-bit floats, uncompressed
+    ExrFloat,
+    /// Radiance RGBE (`.hdr`): an 8-bit mantissa per channel and a shared exponent
+    Hdr,
+    /// Portable float map, 32-bit floats
+    Pfm,
+}
+
+impl ImageFormat {
+    /// Guesses the format from a file extension: `.ppm` is binary PPM, `.png` is 8-bit PNG,
+    /// `.exr` is half-float OpenEXR, `.hdr` is Radiance RGBE and `.pfm` is a portable float
+    /// map.
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    /// Difference between neighbouring levels the format quantizes display colors to, or
+    /// `None` for high dynamic range formats, which store floats.
+    pub fn quantization_step(self) -> Option<f64> {
+        match self {
+            ImageFormat::PpmAscii | ImageFormat::Ppm | ImageFormat::Png => Some(1.0 / 255.0),
+            ImageFormat::Png16 => Some(1.0 / 65535.0),
+            _ => None,
+        }
+    }
+
+    /// Whether the format stores linear radiance, rather than display-ready colors.
+    pub fn is_hdr(self) -> bool {
+        matches!(
+            self,
+            ImageFormat::Exr | ImageFormat::ExrFloat | ImageFormat::Hdr | ImageFormat::Pfm
+        )
+    }
+}
+
+/// Writes the image to `path` in the given format.
+pub fn save_image(
+    path: &Path,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write_image(&mut out, format, width, height, pixels)?;
+    out.flush()
+}
+
+pub fn write_image(
+    out: &mut impl Write,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> io::Result<()> {
+    assert_eq!(pixels.len(), width as usize * height as usize);
+    match format {
+        ImageFormat::PpmAscii => write_ppm_ascii(out, width, height, pixels),
+        ImageFormat::Ppm => write_ppm(out, width, height, pixels),
+        ImageFormat::Png => write_png(out, width, height, pixels, png::BitDepth::Eight),
+        ImageFormat::Png16 => write_png(out, width, height, pixels, png::BitDepth::Sixteen),
+        ImageFormat::Exr | ImageFormat::ExrFloat => {
+            let half = format == ImageFormat::Exr;
+            write_exr(out, width, height, &rgb_channels("", pixels, half))
+        }
+        ImageFormat::Hdr => write_hdr(out, width, height, pixels),
+        ImageFormat::Pfm => write_pfm(out, width, height, pixels),
+    }
+}
+
+/// The `R`, `G` and `B` channels of an image, for `write_exr`, named with `prefix` (e.g.
+/// `albedo.` for a layer).
+pub fn rgb_channels(prefix: &str, pixels: &[Color], half: bool) -> Vec<ExrChannel> {
+    let channel = |name: &str, value: fn(Color) -> f64| {
+        let values = pixels.iter().map(|&color| value(color)).collect();
+        ExrChannel::new(format!("{prefix}{name}"), values, half)
+    };
+    vec![
+        channel("R", Color::x),
+        channel("G", Color::y),
+        channel("B", Color::z),
+    ]
+}
+
+fn to_u8(x: f64) -> u8 {
+    (255.0 * crate::clamp(x, 0.0, 1.0)).round() as u8
+}
+
+fn to_u16(x: f64) -> u16 {
+    (65535.0 * crate::clamp(x, 0.0, 1.0)).round() as u16
+}
+
+fn write_ppm_ascii(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+) -> io::Result<()> {
+    // colors are in ascii
+    writeln!(out, "P3")?;
+
+    // columns, rows
+    writeln!(out, "{} {}", width, height)?;
+
+    // max color
+    writeln!(out, "255")?;
+
+    // RGB triplets
+    for color in pixels {
+        let (ir, ig, ib) = (to_u8(color.x()), to_u8(color.y()), to_u8(color.z()));
+        writeln!(out, "{ir} {ig} {ib}")?;
+    }
+    Ok(())
+}
+
+fn write_ppm(out: &mut impl Write, width: u32, height: u32, pixels: &[Color]) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|color| [to_u8(color.x()), to_u8(color.y()), to_u8(color.z())])
+        .collect();
+    out.write_all(&data)
+}
+
+fn write_png(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    pixels: &[Color],
+    depth: png::BitDepth,
+) -> io::Result<()> {
+    let data: Vec<u8> = match depth {
+        png::BitDepth::Sixteen => pixels
+            .iter()
+            .flat_map(|color| [color.x(), color.y(), color.z()])
+            .flat_map(|x| to_u16(x).to_be_bytes())
+            .collect(),
+        _ => pixels
+            .iter()
+            .flat_map(|color| [to_u8(color.x()), to_u8(color.y()), to_u8(color.z())])
+            .collect(),
+    };
+
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(depth);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// One channel of an OpenEXR image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExrChannel {
+    /// e.g. `R`, or `normal.X` for a channel of the `normal` layer
+    pub name: String,
+    /// A value per pixel, row by row from the top-left.
+    pub values: Vec<f64>,
+    /// Whether to store the values as halfs rather than 32-bit floats. Halfs only hold
+    /// whole numbers exactly up to 2048.
+    pub half: bool,
+}
+
+impl ExrChannel {
+    pub fn new(name: impl Into<String>, values: Vec<f64>, half: bool) -> Self {
+        ExrChannel {
+            name: name.into(),
+            values,
+            half,
+        }
+    }
+}
+
+/// Writes a single-part, uncompressed scanline OpenEXR file with the given channels.
+pub fn write_exr(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    channels: &[ExrChannel],
+) -> io::Result<()> {
+    let pixel_count = width as usize * height as usize;
+    assert!(channels
+        .iter()
+        .all(|channel| channel.values.len() == pixel_count));
+    // Readers expect the channels sorted by name.
+    let mut channels: Vec<&ExrChannel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend(name.as_bytes());
+        header.push(0);
+        header.extend(kind.as_bytes());
+        header.push(0);
+        header.extend((value.len() as i32).to_le_bytes());
+        header.extend(value);
+    };
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend(channel.name.as_bytes());
+        channel_list.push(0);
+        // pixel type (1 = half, 2 = float), "linear", 3 reserved bytes, x and y sampling
+        channel_list.extend((if channel.half { 1i32 } else { 2 }).to_le_bytes());
+        channel_list.extend([0; 4]);
+        channel_list.extend(1i32.to_le_bytes());
+        channel_list.extend(1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    attribute("channels", "chlist", &channel_list);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    header.push(0);
+
+    // magic number, then version 2 with no flags set
+    out.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    out.write_all(&header)?;
+
+    // A table of where each scanline starts, then the scanlines: their y, their size and
+    // each channel's values in turn.
+    let bytes_per_pixel: usize = channels
+        .iter()
+        .map(|channel| if channel.half { 2 } else { 4 })
+        .sum();
+    let line_size = width as usize * bytes_per_pixel;
+    let table_start = 8 + header.len() + height as usize * 8;
+    for y in 0..height as usize {
+        let offset = table_start + y * (8 + line_size);
+        out.write_all(&(offset as u64).to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height as usize {
+        line.clear();
+        let row = y * width as usize..(y + 1) * width as usize;
+        for channel in &channels {
+            for &value in &channel.values[row.clone()] {
+                if channel.half {
+                    line.extend(to_half(value as f32).to_le_bytes());
+                } else {
+                    line.extend((value as f32).to_le_bytes());
+                }
+            }
+        }
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+        out.write_all(&line)?;
+    }
+    Ok(())
+}
+
+// The nearest half-precision float, rounding ties to even.
+fn to_half(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // infinity, or NaN (kept a NaN)
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let round = |value: u32, shift: u32| {
+        let (kept, rest, halfway) = (value >> shift, value & ((1 << shift) - 1), 1 << (shift - 1));
+        if rest > halfway || (rest == halfway && kept & 1 == 1) {
+            kept + 1
+        } else {
+            kept
+        }
+    };
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        // too small for a normal half: a subnormal (or zero) one
+        if exponent < -10 {
+            return sign;
+        }
+        sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16
+    } else {
+        // Rounding up can carry into the exponent, which is still right (even up to
+        // infinity).
+        sign | round((exponent as u32) << 23 | mantissa, 13) as u16
+    }
+}
+
+fn write_hdr(out: &mut impl Write, width: u32, height: u32, pixels: &[Color]) -> io::Result<()> {
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n"
+    )?;
+    // flat scanlines, which any reader takes
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&color| color_to_rgbe(color))
+        .collect();
+    out.write_all(&data)
+}
+
+fn color_to_rgbe(color: Color) -> [u8; 4] {
+    let (r, g, b) = (color.x().max(0.0), color.y().max(0.0), color.z().max(0.0));
+    let brightest = r.max(g).max(b);
+    if brightest < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // brightest = m * 2^exponent with m in [0.5, 1)
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    if brightest / 2f64.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let exponent = exponent.min(127);
+    let scale = 256.0 / 2f64.powi(exponent);
+    let mantissa = |x: f64| (x * scale).min(255.0) as u8;
+    [
+        mantissa(r),
+        mantissa(g),
+        mantissa(b),
+        (exponent + 128) as u8,
+    ]
+}
+
+fn write_pfm(out: &mut impl Write, width: u32, height: u32, pixels: &[Color]) -> io::Result<()> {
+    // a negative scale means little-endian
+    write!(out, "PF\n{width} {height}\n-1.0\n")?;
+    let data: Vec<u8> = pixels
+        .chunks(width.max(1) as usize)
+        .rev()
+        .flatten()
+        .flat_map(|color| [color.x(), color.y(), color.z()])
+        .flat_map(|x| (x as f32).to_le_bytes())
+        .collect();
+    out.write_all(&data)
+}
+
+/// A decoded image, row by row from the top-left.
+///
+/// Low dynamic range images have channels scaled to `[0, 1]` but otherwise as stored
+/// (i.e. not linearized). High dynamic range ones (`linear`) hold linear radiance, with
+/// no upper limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Color>,
+    pub linear: bool,
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+/// Reads a PNG, PPM (`P3` or `P6`), Radiance HDR (`.hdr`) or PFM image, chosen by file
+/// extension.
+pub fn load_image(path: &Path) -> io::Result<Image> {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("png") => read_png(BufReader::new(File::open(path)?)),
+        Some("ppm") => read_ppm(&fs::read(path)?),
+        Some("hdr") => read_hdr(&fs::read(path)?),
+        Some("pfm") => read_pfm(&fs::read(path)?),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unsupported image type, expected .png, .ppm, .hdr or .pfm",
+        )),
+    }
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn read_png(input: impl io::BufRead + io::Seek) -> io::Result<Image> {
+    let mut decoder = png::Decoder::new(input);
+    // palette -> RGB, low bit depths -> 8 bits
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(invalid_data_from)?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| invalid_data("PNG is too large"))?;
+    let mut buf = vec![0; size];
+    let info = reader.next_frame(&mut buf).map_err(invalid_data_from)?;
+    let buf = &buf[..info.buffer_size()];
+
+    let channels = info.color_type.samples();
+    let samples: Vec<f64> = match info.bit_depth {
+        png::BitDepth::Sixteen => buf
+            .chunks_exact(2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.0)
+            .collect(),
+        _ => buf.iter().map(|&b| b as f64 / 255.0).collect(),
+    };
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|px| match px.len() {
+            // grayscale, with or without alpha
+            1 | 2 => Color::new(px[0], px[0], px[0]),
+            // RGB, with or without alpha
+            _ => Color::new(px[0], px[1], px[2]),
+        })
+        .collect();
+
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+        linear: false,
+    })
+}
+
+fn invalid_data_from(err: png::DecodingError) -> io::Error {
+    match err {
+        png::DecodingError::IoError(err) => err,
+        err => invalid_data(err.to_string()),
+    }
+}
+
+// Reads the whitespace-separated tokens of a PPM header, skipping `#` comments.
+struct PpmHeader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl PpmHeader<'_> {
+    fn next_token(&mut self) -> io::Result<String> {
+        let data = self.data;
+        loop {
+            while self.pos < data.len() && data[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            }
+            if self.pos < data.len() && data[self.pos] == b'#' {
+                while self.pos < data.len() && data[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = self.pos;
+        while self.pos < data.len() && !data[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(invalid_data("PPM ends early"));
+        }
+        Ok(String::from_utf8_lossy(&data[start..self.pos]).into_owned())
+    }
+
+    fn next_number(&mut self) -> io::Result<u32> {
+        let token = self.next_token()?;
+        token
+            .parse()
+            .map_err(|_| invalid_data(format!("expected a number in PPM, found `{token}`")))
+    }
+}
+
+// Number of samples in a `format` image of `width` by `height` pixels with `channels`
+// each, refusing empty images and sizes too big to count.
+fn sample_count(format: &str, width: u32, height: u32, channels: usize) -> io::Result<usize> {
+    if width == 0 || height == 0 {
+        return Err(invalid_data(format!(
+            "{format} image is empty ({width}x{height})"
+        )));
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid_data(format!("{format} size {width}x{height} is too big")))
+}
+
+// The `len` bytes of `data` from `start` on, if it has that many.
+fn body(data: &[u8], start: usize, len: Option<usize>) -> Option<&[u8]> {
+    data.get(start..)?.get(..len?)
+}
+
+fn read_ppm(data: &[u8]) -> io::Result<Image> {
+    let mut header = PpmHeader { data, pos: 0 };
+    let magic = header.next_token()?;
+    let width = header.next_number()?;
+    let height = header.next_number()?;
+    let max_value = header.next_number()?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data(format!("invalid PPM max value {max_value}")));
+    }
+    let count = sample_count("PPM", width, height, 3)?;
+
+    let samples: Vec<u32> = match magic.as_str() {
+        "P3" => (0..count)
+            .map(|_| header.next_number())
+            .collect::<io::Result<_>>()?,
+        "P6" => {
+            // a single whitespace byte separates the header from the pixels
+            let start = header.pos + 1;
+            let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+            let body = body(data, start, count.checked_mul(bytes_per_sample))
+                .ok_or_else(|| invalid_data("PPM ends early"))?;
+            if bytes_per_sample == 1 {
+                body.iter().map(|&b| b as u32).collect()
+            } else {
+                body.chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                    .collect()
+            }
+        }
+        _ => return Err(invalid_data(format!("unsupported PPM type `{magic}`"))),
+    };
+
+    let scale = 1.0 / max_value as f64;
+    let pixels = samples
+        .chunks_exact(3)
+        .map(|px| Color::new(px[0] as f64, px[1] as f64, px[2] as f64) * scale)
+        .collect();
+    Ok(Image {
+        width,
+        height,
+        pixels,
+        linear: false,
+    })
+}
+
+// Radiance's RGBE format: a text header ending in a blank line, a resolution line, then
+// scanlines of shared-exponent pixels, usually run-length encoded a channel at a time.
+fn read_hdr(data: &[u8]) -> io::Result<Image> {
+    let mut lines = data.split(|&b| b == b'\n');
+    let mut header_len = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        header_len += line.len() + 1;
+        Some(String::from_utf8_lossy(line).trim().to_string())
+    };
+
+    let magic = next_line().unwrap_or_default();
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err(invalid_data("not a Radiance HDR file"));
+    }
+    loop {
+        match next_line() {
+            None => return Err(invalid_data("HDR header ends early")),
+            Some(line) if line.is_empty() => break,
+            Some(line) => {
+                if let Some(format) = line.strip_prefix("FORMAT=") {
+                    if format != "32-bit_rle_rgbe" {
+                        return Err(invalid_data(format!("unsupported HDR format `{format}`")));
+                    }
+                }
+            }
+        }
+    }
+    let resolution = next_line().unwrap_or_default();
+    let (width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (width.parse::<u32>(), height.parse::<u32>()),
+        _ => {
+            return Err(invalid_data(format!(
+                "unsupported HDR orientation `{resolution}`, expected `-Y <height> +X <width>`"
+            )))
+        }
+    };
+    let (width, height) = match (width, height) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(invalid_data(format!("invalid HDR size `{resolution}`"))),
+    };
+    let count = sample_count("HDR", width, height, 1)?;
+    let (width, height) = (width as usize, height as usize);
+
+    let mut body = &data[header_len.min(data.len())..];
+    // Even run-length encoded, every scanline takes some bytes, so a file too short for
+    // its size is caught before allocating for it.
+    if body.len() / hdr_min_scanline_len(width) < height {
+        return Err(invalid_data("HDR ends early"));
+    }
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        body = read_hdr_scanline(body, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+    }
+    Ok(Image {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+        linear: true,
+    })
+}
+
+// The fewest bytes a scanline `width` pixels wide can be stored in: runs of up to 127
+// pixels, two bytes each per channel, after the 4-byte start if it can be run-length
+// encoded, or 4 bytes a pixel if not.
+fn hdr_min_scanline_len(width: usize) -> usize {
+    if (8..0x8000).contains(&width) {
+        4 + 4 * 2 * width.div_ceil(127)
+    } else {
+        width.saturating_mul(4)
+    }
+}
+
+// Decodes one scanline into `scanline`, returning the rest of the data.
+fn read_hdr_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> io::Result<&'a [u8]> {
+    let ends_early = || invalid_data("HDR ends early");
+    let width = scanline.len();
+    // New-style run-length encoding starts each scanline with 2, 2 and its width.
+    let is_rle = (8..0x8000).contains(&width)
+        && data.len() >= 4
+        && data[0] == 2
+        && data[1] == 2
+        && ((data[2] as usize) << 8 | data[3] as usize) == width;
+    if !is_rle {
+        let bytes = data.get(..width * 4).ok_or_else(ends_early)?;
+        for (pixel, rgbe) in scanline.iter_mut().zip(bytes.chunks_exact(4)) {
+            pixel.copy_from_slice(rgbe);
+        }
+        return Ok(&data[width * 4..]);
+    }
+
+    let mut pos = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *data.get(pos).ok_or_else(ends_early)? as usize;
+            pos += 1;
+            if count == 0 {
+                return Err(invalid_data("bad HDR run"));
+            }
+            if count > 128 {
+                // a run of one value
+                let run = count - 128;
+                let value = *data.get(pos).ok_or_else(ends_early)?;
+                pos += 1;
+                let pixels = scanline
+                    .get_mut(x..x + run)
+                    .ok_or_else(|| invalid_data("bad HDR run"))?;
+                pixels.iter_mut().for_each(|pixel| pixel[channel] = value);
+                x += run;
+            } else {
+                // `count` literal values
+                let values = data.get(pos..pos + count).ok_or_else(ends_early)?;
+                pos += count;
+                let pixels = scanline
+                    .get_mut(x..x + count)
+                    .ok_or_else(|| invalid_data("bad HDR run"))?;
+                for (pixel, &value) in pixels.iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(&data[pos..])
+}
+
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    // the mantissas are fractions of 256, scaled by 2^(e - 128)
+    let scale = 2f64.powi(e as i32 - 136);
+    Color::new(r as f64 + 0.5, g as f64 + 0.5, b as f64 + 0.5) * scale
+}
+
+// Portable float map: a PPM-like header (`PF` for RGB, `Pf` for grayscale), where the
+// "max value" is a scale whose sign gives the byte order, then 32-bit floats from the
+// bottom row up.
+fn read_pfm(data: &[u8]) -> io::Result<Image> {
+    let mut header = PpmHeader { data, pos: 0 };
+    let magic = header.next_token()?;
+    let channels = match magic.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid_data(format!("unsupported PFM type `{magic}`"))),
+    };
+    let width = header.next_number()?;
+    let height = header.next_number()?;
+    let count = sample_count("PFM", width, height, channels)?;
+    let scale = header.next_token()?;
+    let little_endian = match scale.parse::<f64>() {
+        Ok(scale) if scale != 0.0 => scale < 0.0,
+        _ => return Err(invalid_data(format!("invalid PFM scale `{scale}`"))),
+    };
+
+    let start = header.pos + 1;
+    let body =
+        body(data, start, count.checked_mul(4)).ok_or_else(|| invalid_data("PFM ends early"))?;
+    let samples: Vec<f64> = body
+        .chunks_exact(4)
+        .map(|b| {
+            let bytes = [b[0], b[1], b[2], b[3]];
+            let value = if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            };
+            value as f64
+        })
+        .collect();
+    let rows: Vec<&[f64]> = samples.chunks_exact(width as usize * channels).collect();
+    let pixels = rows
+        .iter()
+        .rev()
+        .flat_map(|row| row.chunks_exact(channels))
+        .map(|px| match px {
+            [y] => Color::new(*y, *y, *y),
+            _ => Color::new(px[0], px[1], px[2]),
+        })
+        .collect();
+    Ok(Image {
+        width,
+        height,
+        pixels,
+        linear: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn pixels() -> Vec<Color> {
+        vec![
+            Color::new(1.0, 0.0, 0.5),
+            Color::new(0.25, 2.0, -1.0),
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+        ]
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut out = Vec::new();
+        write_image(&mut out, ImageFormat::Ppm, 2, 2, &pixels()).unwrap();
+        let mut expected = b"P6\n2 2\n255\n".to_vec();
+        expected.extend([255, 0, 128, 64, 255, 0, 0, 0, 0, 255, 255, 255]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_read_ppm() {
+        let mut binary = Vec::new();
+        write_image(&mut binary, ImageFormat::Ppm, 2, 2, &pixels()).unwrap();
+        let mut ascii = Vec::new();
+        write_image(&mut ascii, ImageFormat::PpmAscii, 2, 2, &pixels()).unwrap();
+
+        let image = read_ppm(&binary).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixel(1, 0), Color::new(64.0, 255.0, 0.0) / 255.0);
+        assert_eq!(read_ppm(&ascii).unwrap(), image);
+
+        let commented = b"P3\n# a comment\n1 1\n255\n255 0 0\n";
+        assert_eq!(
+            read_ppm(commented).unwrap().pixels,
+            vec![Color::new(1.0, 0.0, 0.0)]
+        );
+        assert!(read_ppm(b"P6\n2 2\n255\n\x00").is_err());
+        // headers that don't describe an image that fits in the file
+        assert!(read_ppm(b"P6\n0 2\n255\n").is_err());
+        assert!(read_ppm(b"P6\n4294967295 4294967295\n65535\n\x00").is_err());
+    }
+
+    #[test]
+    fn test_write_png16_round_trip() {
+        let mut out = Vec::new();
+        write_image(&mut out, ImageFormat::Png16, 2, 2, &pixels()).unwrap();
+
+        let mut reader = png::Decoder::new(Cursor::new(out)).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size().unwrap()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.width, info.height), (2, 2));
+        assert_eq!(info.bit_depth, png::BitDepth::Sixteen);
+        assert_eq!(&buf[..6], &[0xff, 0xff, 0, 0, 0x80, 0x00]);
+        assert_eq!(&buf[6..8], &to_u16(0.25).to_be_bytes());
+    }
+
+    #[test]
+    fn test_read_hdr() {
+        let header = b"#?RADIANCE\n# made by hand\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n";
+        let mut data = header.to_vec();
+        // a flat scanline: (1, 0.5, 0) everywhere
+        for _ in 0..8 {
+            data.extend([128, 64, 0, 129]);
+        }
+        // a run-length encoded one: red is a run, green literals, blue and exponent runs
+        data.extend([2, 2, 0, 8]);
+        data.extend([128 + 8, 128]);
+        data.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        data.extend([128 + 8, 0]);
+        data.extend([128 + 8, 130]);
+
+        let image = read_hdr(&data).unwrap();
+        assert!(image.linear);
+        assert_eq!((image.width, image.height), (8, 2));
+        let expected = Color::new(128.5 / 128.0, 64.5 / 128.0, 0.5 / 128.0);
+        assert_eq!(image.pixel(3, 0), expected);
+        let expected = Color::new(128.5 / 64.0, 32.5 / 64.0, 0.5 / 64.0);
+        assert_eq!(image.pixel(2, 1), expected);
+
+        assert!(read_hdr(&data[..data.len() - 1]).is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n+Y 2 +X 8\n").is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 0 +X 8\n").is_err());
+        // far too big for the data, so it mustn't be allocated
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 100000 +X 100000\n\x02\x02").is_err());
+        assert!(read_hdr(b"#?RADIANCE\n\n-Y 4294967295 +X 4294967295\n").is_err());
+    }
+
+    #[test]
+    fn test_read_pfm() {
+        let mut data = b"PF\n2 2\n-1.0\n".to_vec();
+        // bottom row first
+        for x in [
+            0.0f32, 0.0, 0.0, 4.5, 1.0, 0.25, 1.0, 2.0, 3.0, -1.0, 0.5, 0.0,
+        ] {
+            data.extend(x.to_le_bytes());
+        }
+        let image = read_pfm(&data).unwrap();
+        assert!(image.linear);
+        assert_eq!(image.pixel(0, 0), Color::new(1.0, 2.0, 3.0));
+        assert_eq!(image.pixel(1, 1), Color::new(4.5, 1.0, 0.25));
+
+        let mut data = b"Pf 1 1 1\n".to_vec();
+        data.extend(2.0f32.to_be_bytes());
+        assert_eq!(
+            read_pfm(&data).unwrap().pixels,
+            vec![Color::new(2.0, 2.0, 2.0)]
+        );
+        assert!(read_pfm(b"PF 1 1 -1\n\x00\x00").is_err());
+        assert!(read_pfm(b"PF\n0 1\n-1\n").is_err());
+        assert!(read_pfm(b"PF\n4294967295 4294967295\n-1\n").is_err());
+    }
+
+    #[test]
+    fn test_to_half() {
+        assert_eq!(to_half(0.0), 0);
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(to_half(0.1), 0x2e66);
+        assert_eq!(to_half(65504.0), 0x7bff);
+        assert_eq!(to_half(65520.0), 0x7c00);
+        assert_eq!(to_half(1e9), 0x7c00);
+        assert_eq!(to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(to_half(2f32.powi(-26)), 0);
+        assert_eq!(to_half(f32::NAN) & 0x7e00, 0x7e00);
+    }
+
+    #[test]
+    fn test_write_exr() {
+        let mut out = Vec::new();
+        write_image(&mut out, ImageFormat::ExrFloat, 2, 2, &pixels()).unwrap();
+        assert_eq!(&out[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        assert_eq!(&out[8..17], b"channels\0");
+
+        // the offset table points at the last scanline: y = 1, 2 pixels of 3 floats
+        let table = out.len() - 2 * (8 + 24) - 16;
+        let last = u64::from_le_bytes(out[table + 8..table + 16].try_into().unwrap()) as usize;
+        assert_eq!(last, out.len() - (8 + 24));
+        assert_eq!(&out[last..last + 8], &[1, 0, 0, 0, 24, 0, 0, 0]);
+        // channels are stored B, G, R
+        let values: Vec<f32> = out[last + 8..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, vec![0.0, 1.0, 0.0, 1.0, 0.0, 1.0]);
+
+        let mut half = Vec::new();
+        write_image(&mut half, ImageFormat::Exr, 2, 2, &pixels()).unwrap();
+        assert_eq!(half.len(), out.len() - 2 * 12);
+    }
+
+    #[test]
+    fn test_hdr_and_pfm_round_trip() {
+        let pixels = vec![
+            Color::new(1.0, 0.5, 0.25),
+            Color::new(1000.0, 0.001, 0.0),
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(3.0, 3.5, 4.0),
+        ];
+        let mut out = Vec::new();
+        write_image(&mut out, ImageFormat::Pfm, 2, 2, &pixels).unwrap();
+        let image = read_pfm(&out).unwrap();
+        for (read, written) in image.pixels.iter().zip(&pixels) {
+            assert!((*read - *written).length() < 1e-4 * written.length().max(1.0));
+        }
+
+        let mut out = Vec::new();
+        write_image(&mut out, ImageFormat::Hdr, 2, 2, &pixels).unwrap();
+        let image = read_hdr(&out).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        for (read, written) in image.pixels.iter().zip(&pixels) {
+            // RGBE keeps about 1% of the brightest channel
+            assert!(
+                (*read - *written).length() <= 0.01 * written.max_component(),
+                "{read:?}"
+            );
+        }
+        assert_eq!(color_to_rgbe(Color::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(color_to_rgbe(Color::new(-1.0, 0.0, 0.0)), [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,564 @@
+//! Integrators: the different ways of turning a camera ray into a color.
+//!
+//! `PathIntegrator` computes the actual image; the others are cheaper approximations or
+//! debugging views of the same scene.
+
+use crate::material::ScatterResult;
+use crate::sampler::Sampler;
+use crate::sampling::{cosine_hemisphere, Onb};
+use crate::vec3::{Color, Point3, Vec3, COLOR_BLACK, COLOR_WHITE};
+use crate::{Background, HitList, HitRecord, Hittable, Ray, INF};
+
+/// Everything an integrator looks at: the objects, which of them are lights (for light
+/// sampling) and what's behind them. An environment map background is sampled as a
+/// light too.
+pub struct Scene<'a> {
+    pub world: &'a dyn Hittable,
+    pub lights: &'a HitList,
+    pub background: &'a Background,
+}
+
+impl Scene<'_> {
+    /// Whether there's any light to sample: emitting objects or a sampled background.
+    pub fn has_lights(&self) -> bool {
+        !self.lights.objects.is_empty() || self.background.is_sampled()
+    }
+
+    // How often `light_direction` samples the background rather than the lights: never
+    // if it isn't sampled, always if there are no lights, and otherwise half the time.
+    fn background_share(&self) -> f64 {
+        match (self.background.is_sampled(), self.lights.objects.is_empty()) {
+            (false, _) => 0.0,
+            (true, true) => 1.0,
+            (true, false) => 0.5,
+        }
+    }
+
+    /// A direction from `origin` towards some light, as the lights are at `time`.
+    fn light_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let share = self.background_share();
+        if share == 1.0 || (share > 0.0 && sampler.get_1d() < share) {
+            if let Some(dir) = self.background.sample_direction(sampler.get_2d()) {
+                return dir;
+            }
+        }
+        self.lights.random_direction(origin, time, sampler)
+    }
+
+    // MIS weight for light found along `ray`, which a bounce picked with density
+    // `bsdf_pdf`, against light sampling finding it.
+    fn mis_weight(&self, ray: &Ray, bsdf_pdf: f64) -> f64 {
+        power_heuristic(bsdf_pdf, self.light_pdf(ray.orig, ray.dir, ray.time))
+    }
+
+    /// Density of `light_direction` picking `dir`.
+    fn light_pdf(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        let share = self.background_share();
+        let mut pdf = 0.0;
+        if share > 0.0 {
+            pdf += share * self.background.pdf(dir);
+        }
+        if share < 1.0 {
+            pdf += (1.0 - share) * self.lights.pdf_value(origin, dir, time);
+        }
+        pdf
+    }
+}
+
+pub trait Integrator: Send + Sync {
+    /// Light arriving back along `ray`, drawing random numbers from `sampler`.
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color;
+}
+
+/// Full global illumination, following up to `max_depth` bounces.
+///
+/// Besides following the bounces chosen by materials, every diffuse bounce also samples a
+/// direction towards one of the scene's lights or its environment map (next-event
+/// estimation). The two strategies are combined with multiple importance sampling, so
+/// each keeps the light it finds with low noise. With no lights, this is plain path
+/// tracing.
+///
+/// After `roulette_depth` bounces, paths are ended at random (Russian roulette) with a
+/// probability that grows as they carry less light, and the survivors are brightened to
+/// make up for it. That keeps long paths from costing much without darkening the image,
+/// so `max_depth` can be set high.
+pub struct PathIntegrator {
+    pub max_depth: i32,
+    pub roulette_depth: i32,
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = COLOR_BLACK;
+        // how much of the light arriving along `ray` reaches the camera
+        let mut throughput = COLOR_WHITE;
+        let mut ray = *ray;
+        // The density the previous bounce chose `ray`'s direction with, if light sampling
+        // could have found the same light (i.e. the bounce wasn't specular).
+        let mut bsdf_pdf = None;
+
+        for depth in 0..self.max_depth {
+            let rec = match scene.world.hit(&ray, 0.001, INF) {
+                Some(rec) => rec,
+                None => {
+                    let mut background = scene.background.color(ray.dir);
+                    if let Some(bsdf_pdf) = bsdf_pdf {
+                        background *= scene.mis_weight(&ray, bsdf_pdf);
+                    }
+                    radiance += throughput * background;
+                    break;
+                }
+            };
+
+            let mut emitted = rec.mat_ptr.emitted(&ray, &rec);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                if emitted != COLOR_BLACK {
+                    emitted *= scene.mis_weight(&ray, bsdf_pdf);
+                }
+            }
+            radiance += throughput * emitted;
+
+            let out = match rec.mat_ptr.scatter(&ray, &rec, sampler) {
+                Some(out) => out,
+                None => break,
+            };
+            if out.pdf.is_some() && scene.has_lights() {
+                // The last bounce is never traced, so light sampling has to find it all.
+                let bsdf_traced = depth < self.max_depth - 1;
+                radiance += throughput * sample_light(&ray, scene, &rec, bsdf_traced, sampler);
+            }
+            throughput = throughput * out.attenuation;
+
+            if depth >= self.roulette_depth {
+                let survival = throughput.max_component().min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+            bsdf_pdf = out.pdf;
+            ray = out.scattered;
+        }
+        radiance
+    }
+}
+
+/// Only light that reaches a surface straight from an emitter or the background, after
+/// following up to `max_depth` mirror and glass bounces to find that surface.
+///
+/// Much less noisy than `PathIntegrator`, but with black shadows, since nothing is lit by
+/// light bouncing off other objects.
+pub struct DirectLightingIntegrator {
+    pub max_depth: i32,
+}
+
+impl Integrator for DirectLightingIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        trace_direct(ray, scene, self.max_depth, sampler)
+    }
+}
+
+fn trace_direct(ray: &Ray, scene: &Scene, depth: i32, sampler: &mut dyn Sampler) -> Color {
+    if depth <= 0 {
+        return COLOR_BLACK;
+    }
+
+    let rec = match scene.world.hit(ray, 0.001, INF) {
+        Some(rec) => rec,
+        None => return scene.background.color(ray.dir),
+    };
+    let emitted = rec.mat_ptr.emitted(ray, &rec);
+    let out = match rec.mat_ptr.scatter(ray, &rec, sampler) {
+        Some(out) => out,
+        None => return emitted,
+    };
+    let bsdf_pdf = match out.pdf {
+        Some(bsdf_pdf) => bsdf_pdf,
+        // specular: keep looking for a surface to light
+        None => {
+            return emitted
+                + out.attenuation * trace_direct(&out.scattered, scene, depth - 1, sampler)
+        }
+    };
+
+    let direct = if scene.has_lights() {
+        sample_light(ray, scene, &rec, true, sampler)
+    } else {
+        COLOR_BLACK
+    };
+    // What the scattered ray sees directly, weighted the same way `PathIntegrator` would.
+    let scattered = &out.scattered;
+    let found = match scene.world.hit(scattered, 0.001, INF) {
+        Some(hit) => hit.mat_ptr.emitted(scattered, &hit),
+        None => scene.background.color(scattered.dir),
+    };
+    let found = found * scene.mis_weight(scattered, bsdf_pdf);
+    emitted + direct + out.attenuation * found
+}
+
+// Light reaching `rec` directly from a random point on one of the scene's lights (or a
+// bright part of the background), weighted for MIS against finding it by scattering if
+// the caller traces the scattered ray (`bsdf_traced`) and counts what it finds.
+fn sample_light(
+    ray: &Ray,
+    scene: &Scene,
+    rec: &HitRecord,
+    bsdf_traced: bool,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let dir = scene.light_direction(rec.p, ray.time, sampler);
+    let light_pdf = scene.light_pdf(rec.p, dir, ray.time);
+    if light_pdf <= 0.0 {
+        return COLOR_BLACK;
+    }
+    let f = rec.mat_ptr.eval(ray, rec, dir);
+    if f == COLOR_BLACK {
+        return COLOR_BLACK;
+    }
+
+    // The shadow ray sees whatever is actually first in that direction.
+    let shadow_ray = Ray::with_time(rec.p, dir, ray.time);
+    let emitted = match scene.world.hit(&shadow_ray, 0.001, INF) {
+        Some(light_rec) => light_rec.mat_ptr.emitted(&shadow_ray, &light_rec),
+        None if scene.background.is_sampled() => scene.background.color(dir),
+        None => COLOR_BLACK,
+    };
+    if emitted == COLOR_BLACK {
+        return COLOR_BLACK;
+    }
+    let weight = if bsdf_traced {
+        power_heuristic(light_pdf, rec.mat_ptr.scattering_pdf(ray, rec, dir))
+    } else {
+        1.0
+    };
+    f * emitted * (weight / light_pdf)
+}
+
+/// MIS weight for a sample drawn with density `pdf` when another strategy could have
+/// drawn it with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Ambient occlusion: white where a surface can see the open sky, darker the more it's
+/// hemmed in by other objects within `distance`, ignoring materials and lights.
+///
+/// Each ray checks one cosine-weighted direction, so the image averages out over the
+/// samples per pixel. Rays that miss everything are white.
+pub struct AmbientOcclusionIntegrator {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let rec = match scene.world.hit(ray, 0.001, INF) {
+            Some(rec) => rec,
+            None => return COLOR_WHITE,
+        };
+        let dir = Onb::from_w(rec.normal).local(cosine_hemisphere(sampler.get_2d()));
+        let probe = Ray::with_time(rec.p, dir, ray.time);
+        match scene.world.hit(&probe, 0.001, self.distance) {
+            Some(_) => COLOR_BLACK,
+            None => COLOR_WHITE,
+        }
+    }
+}
+
+/// What a `DebugIntegrator` shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugView {
+    /// The normal (facing the camera) where the ray first hits, mapped from `[-1, 1]` to
+    /// `[0, 1]` per axis.
+    Normal,
+    /// `1 / (1 + distance)` to the first hit: white up close, fading to black far away.
+    Depth,
+    /// The color of the first surface hit: its albedo, or what it emits if it's a light.
+    /// Glass is white, and rays that miss show the background.
+    Albedo,
+}
+
+/// Shades each pixel with a property of the first surface its ray hits, to check
+/// geometry and materials without waiting for lighting to converge.
+pub struct DebugIntegrator {
+    pub view: DebugView,
+}
+
+impl Integrator for DebugIntegrator {
+    fn li(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let rec = match scene.world.hit(ray, 0.001, INF) {
+            Some(rec) => rec,
+            None if self.view == DebugView::Albedo => return scene.background.color(ray.dir),
+            None => return COLOR_BLACK,
+        };
+        match self.view {
+            DebugView::Normal => (rec.normal + COLOR_WHITE) * 0.5,
+            DebugView::Depth => {
+                let shade = 1.0 / (1.0 + rec.t * ray.dir.length());
+                Color::new(shade, shade, shade)
+            }
+            DebugView::Albedo => albedo(ray, &rec, sampler),
+        }
+    }
+}
+
+/// The color of the surface `ray` hit: its albedo, or what it emits if it's a light.
+pub(crate) fn albedo(ray: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler) -> Color {
+    // Every material's attenuation is its albedo (its reflectance, divided by the density
+    // it picked the direction with, comes out the same).
+    match rec.mat_ptr.scatter(ray, rec, sampler) {
+        Some(ScatterResult { attenuation, .. }) => attenuation,
+        None => rec.mat_ptr.emitted(ray, rec),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use std::f64::consts::PI;
+
+    use crate::environment::EnvironmentMap;
+    use crate::image_io::Image;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::quad::{Plane, Quad};
+    use crate::sampler::IndependentSampler;
+    use crate::util::seed_rng;
+    use crate::vec3::{Point3, Vec3};
+    use crate::Sphere;
+
+    // a gray floor under a small square light
+    fn lit_floor() -> (HitList, HitList) {
+        let mut world = HitList::new();
+        let light = Arc::new(Quad::new(
+            Point3::new(-0.5, 1.0, -0.5),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Arc::new(DiffuseLight::new(COLOR_WHITE)),
+        ));
+        let mut lights = HitList::new();
+        lights.add(Box::new(Arc::clone(&light)));
+        world.add(Box::new(light));
+        world.add(Box::new(Quad::new(
+            Point3::new(-50.0, 0.0, -50.0),
+            Vec3::new(100.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 100.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        (world, lights)
+    }
+
+    // mean and variance of the red channel seen looking straight down at the floor
+    fn estimate(integrator: &dyn Integrator, scene: &Scene, n: usize) -> (f64, f64) {
+        let samples: Vec<f64> = (0..n)
+            .map(|_| {
+                let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -1.0, 0.0));
+                integrator.li(&r, scene, &mut IndependentSampler).x()
+            })
+            .collect();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let variance = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn test_light_sampling_matches_path_tracing() {
+        let (world, lights) = lit_floor();
+        let background = Background::Solid(COLOR_BLACK);
+        let no_lights = HitList::new();
+        let plain_scene = Scene {
+            world: &world,
+            lights: &no_lights,
+            background: &background,
+        };
+        let scene = Scene {
+            lights: &lights,
+            ..plain_scene
+        };
+
+        seed_rng(1);
+        let n = 20_000;
+        let path = PathIntegrator {
+            max_depth: 2,
+            roulette_depth: 2,
+        };
+        let (plain, plain_variance) = estimate(&path, &plain_scene, n);
+        let (sampled, sampled_variance) = estimate(&path, &scene, n);
+        assert!(
+            (plain - sampled).abs() < 0.05 * plain,
+            "{plain} vs {sampled}"
+        );
+        assert!(sampled_variance < 0.25 * plain_variance);
+
+        // With only one bounce to the light, direct lighting is the whole answer.
+        let direct = DirectLightingIntegrator { max_depth: 2 };
+        let (direct, _) = estimate(&direct, &scene, n);
+        assert!((direct - sampled).abs() < 0.05 * sampled, "{direct}");
+
+        // Stopping at the floor, light sampling alone has to find all of it.
+        let last = PathIntegrator {
+            max_depth: 1,
+            roulette_depth: 1,
+        };
+        let (last, _) = estimate(&last, &scene, n);
+        assert!((last - direct).abs() < 0.05 * direct, "{last} vs {direct}");
+    }
+
+    #[test]
+    fn test_environment_light_sampling() {
+        // a dim sky with a small, bright sun, over an endless gray floor
+        let (width, height) = (64, 32);
+        let mut pixels = vec![Color::new(0.2, 0.3, 0.5); width * height];
+        pixels[6 * width + 40] = Color::new(5000.0, 4000.0, 3000.0);
+        let image = Image {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+            linear: true,
+        };
+        let map = EnvironmentMap::new(image, 0.0, 1.0);
+        let background = Background::Environment(Arc::new(map));
+        let mut world = HitList::new();
+        world.add(Box::new(Plane::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let lights = HitList::new();
+        let scene = Scene {
+            world: &world,
+            lights: &lights,
+            background: &background,
+        };
+
+        // The floor reflects (albedo / π) ∫ L cos θ over the sky. Each pixel is constant,
+        // and ∫ cos θ sin θ dθ over a row is half the difference of sin² θ at its edges.
+        let Background::Environment(map) = &background else {
+            unreachable!()
+        };
+        let mut expected = 0.0;
+        for y in 0..height / 2 {
+            let (theta0, theta1) = (
+                y as f64 * PI / height as f64,
+                (y + 1) as f64 * PI / height as f64,
+            );
+            let row = (theta1.sin().powi(2) - theta0.sin().powi(2)) / 2.0;
+            for x in 0..width {
+                let phi = 2.0 * PI * (x as f64 + 0.5) / width as f64;
+                let theta = (theta0 + theta1) / 2.0;
+                let dir = Vec3::new(
+                    theta.sin() * (phi - PI).sin(),
+                    theta.cos(),
+                    -theta.sin() * (phi - PI).cos(),
+                );
+                expected += map.color(dir).x() * row * 2.0 * PI / width as f64;
+            }
+        }
+        expected *= 0.5 / PI;
+
+        seed_rng(5);
+        let path = PathIntegrator {
+            max_depth: 2,
+            roulette_depth: 2,
+        };
+        let (mean, variance) = estimate(&path, &scene, 4000);
+        assert!(
+            (mean - expected).abs() < 0.03 * expected,
+            "{mean} vs {expected}"
+        );
+        // Scattering alone would find the sun about one time in a few hundred.
+        assert!(variance < mean * mean, "{variance}");
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        // a small light inside a closed gray sphere, so light keeps bouncing a long time
+        let mut world = HitList::new();
+        let light = Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            0.2,
+            Arc::new(DiffuseLight::new(COLOR_WHITE)),
+        ));
+        let mut lights = HitList::new();
+        lights.add(Box::new(Arc::clone(&light)));
+        world.add(Box::new(light));
+        world.add(Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.8))),
+        )));
+        let background = Background::Solid(COLOR_BLACK);
+        let scene = Scene {
+            world: &world,
+            lights: &lights,
+            background: &background,
+        };
+
+        seed_rng(4);
+        let n = 4000;
+        let mean = |roulette_depth| {
+            let path = PathIntegrator {
+                max_depth: 100,
+                roulette_depth,
+            };
+            (0..n)
+                .map(|_| {
+                    let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0));
+                    path.li(&r, &scene, &mut IndependentSampler).x()
+                })
+                .sum::<f64>()
+                / n as f64
+        };
+        let (exact, roulette) = (mean(100), mean(0));
+        assert!(
+            (exact - roulette).abs() < 0.05 * exact,
+            "{exact} vs {roulette}"
+        );
+    }
+
+    #[test]
+    fn test_debug_and_ao_integrators() {
+        let (world, lights) = lit_floor();
+        let background = Background::Solid(Color::new(0.1, 0.2, 0.3));
+        let scene = Scene {
+            world: &world,
+            lights: &lights,
+            background: &background,
+        };
+        let down = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, -2.0, 0.0));
+        let up = Ray::new(Point3::new(3.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let debug = |view| DebugIntegrator { view };
+
+        let normal = debug(DebugView::Normal).li(&down, &scene, &mut IndependentSampler);
+        assert_eq!(normal, Color::new(0.5, 1.0, 0.5));
+        let depth = debug(DebugView::Depth).li(&down, &scene, &mut IndependentSampler);
+        assert_eq!(depth, Color::new(1.0 / 1.5, 1.0 / 1.5, 1.0 / 1.5));
+        assert_eq!(
+            debug(DebugView::Albedo).li(&down, &scene, &mut IndependentSampler),
+            Color::new(0.5, 0.5, 0.5)
+        );
+        assert_eq!(
+            debug(DebugView::Albedo).li(&up, &scene, &mut IndependentSampler),
+            background.color(up.dir)
+        );
+        assert_eq!(
+            debug(DebugView::Normal).li(&up, &scene, &mut IndependentSampler),
+            COLOR_BLACK
+        );
+
+        // The floor right under the light is partly occluded by it, but not within 0.1.
+        seed_rng(3);
+        let ao = |distance| {
+            let integrator = AmbientOcclusionIntegrator { distance };
+            estimate(&integrator, &scene, 2000).0
+        };
+        assert_eq!(ao(0.1), 1.0);
+        let occluded = ao(INF);
+        assert!(0.4 < occluded && occluded < 0.9, "{occluded}");
+    }
+}
@@ -1,57 +1,131 @@
-use std::{cmp::Ordering, sync::Arc};
+use std::{cmp::Ordering, f64::consts::PI, sync::Arc};
 
+pub mod aabb;
+pub mod adaptive;
+pub mod aov;
+pub mod bvh;
+pub mod environment;
+pub mod film;
+pub mod image_io;
+pub mod integrator;
 pub mod material;
+pub mod medium;
+pub mod obj;
+pub mod perlin;
+pub mod quad;
+pub mod sampler;
+pub mod sampling;
+pub mod scene_file;
+pub mod sky;
+pub mod texture;
+pub mod tonemap;
+pub mod transform;
+pub mod triangle;
 pub mod util;
 pub mod vec3;
 
+use aabb::Aabb;
+use environment::EnvironmentMap;
 use material::Material;
+use sampler::Sampler;
+use sampling::{
+    concentric_disk, uniform_cone, uniform_cone_pdf, uniform_sphere, uniform_sphere_pdf, Onb,
+};
+use sky::PhysicalSky;
 use util::degrees_to_radians;
 use vec3::{dot, Color, Point3, Vec3, COLOR_BLACK, COLOR_WHITE};
 
 // Constants
 const INF: f64 = f64::INFINITY;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    // when the ray was fired, within the camera's shutter interval
+    time: f64,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Ray {
-        Ray { orig, dir }
+        Ray::with_time(orig, dir, 0.0)
+    }
+
+    pub fn with_time(orig: Point3, dir: Vec3, time: f64) -> Ray {
+        Ray { orig, dir, time }
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
     }
 
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + self.dir * t
     }
+}
 
-    pub fn color(self, world: &impl Hittable, depth: i32) -> Color {
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            // eprintln!("depth <= 0");
-            return COLOR_BLACK;
-        }
-
-        match world.hit(&self, 0.001, INF) {
-            Some(rec) => {
-                // eprintln!("hit? yes");
-                let out = rec.mat_ptr.scatter(&self, &rec);
-                match out {
-                    Some(out) => {
-                        // eprintln!("attenuation: {:?}", out.attenuation);
-                        // eprintln!("scattered:{:?}", out.scattered);
-                        out.attenuation * out.scattered.color(world, depth - 1)
-                    }
-                    None => COLOR_BLACK,
-                }
-            }
-            None => {
-                // eprintln!("hit? no");
-                let unit_direction = self.dir.unit_vector();
+/// What a ray sees when it doesn't hit anything.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    /// Blend from `horizon` (looking straight down) to `zenith` (looking straight up).
+    Gradient { horizon: Color, zenith: Color },
+    /// A single color everywhere; black for scenes lit only by their own lights.
+    Solid(Color),
+    /// An image of the surroundings, which integrators also sample as a light.
+    Environment(Arc<EnvironmentMap>),
+    /// A daylight sky with the sun in it, which integrators sample as a light.
+    Sky(Arc<PhysicalSky>),
+}
+
+impl Background {
+    pub fn color(&self, dir: Vec3) -> Color {
+        match self {
+            Background::Gradient { horizon, zenith } => {
+                let unit_direction = dir.unit_vector();
                 let t = 0.5 * (unit_direction.y() + 1.0);
-                COLOR_WHITE * (1.0 - t) + Color::new(0.5, 0.7, 1.0) * t
+                *horizon * (1.0 - t) + *zenith * t
             }
+            Background::Solid(color) => *color,
+            Background::Environment(map) => map.color(dir),
+            Background::Sky(sky) => sky.color(dir),
+        }
+    }
+
+    /// Whether `sample_direction` can pick directions towards the background's light.
+    pub fn is_sampled(&self) -> bool {
+        match self {
+            Background::Environment(_) => true,
+            Background::Sky(sky) => sky.sun_is_up(),
+            _ => false,
+        }
+    }
+
+    /// A direction towards the background's light, if it's sampled, from a sample in
+    /// `[0, 1)²`.
+    pub fn sample_direction(&self, sample: (f64, f64)) -> Option<Vec3> {
+        match self {
+            Background::Environment(map) => Some(map.sample(sample)),
+            Background::Sky(sky) if sky.sun_is_up() => Some(sky.sample_sun(sample)),
+            _ => None,
+        }
+    }
+
+    /// Density of `sample_direction` picking `dir`; 0 if the background isn't sampled.
+    pub fn pdf(&self, dir: Vec3) -> f64 {
+        match self {
+            Background::Environment(map) => map.pdf(dir),
+            Background::Sky(sky) => sky.sun_pdf(dir),
+            _ => 0.0,
+        }
+    }
+}
+
+impl Default for Background {
+    /// The white-to-blue sky from the book.
+    fn default() -> Self {
+        Background::Gradient {
+            horizon: COLOR_WHITE,
+            zenith: Color::new(0.5, 0.7, 1.0),
         }
     }
 }
@@ -60,12 +134,19 @@ pub struct HitRecord {
     p: Point3,
     normal: Vec3,
     t: f64,
+    // surface coordinates of the hit, for textures
+    u: f64,
+    v: f64,
     front_face: bool,
     mat_ptr: Arc<dyn Material>,
+    // the object hit, once tagged by `HitList::with_object_ids`; 0 otherwise
+    object_id: u32,
+    // `mat_ptr`'s `Material::id`
+    material_id: u32,
 }
 
 impl HitRecord {
-    fn with_face_normal(self: Self, r: &Ray, outward_normal: Vec3) -> HitRecord {
+    fn with_face_normal(self, r: &Ray, outward_normal: Vec3) -> HitRecord {
         let (normal, front_face) = if dot(r.dir, outward_normal) > 0.0 {
             (-outward_normal, false)
         } else {
@@ -73,17 +154,50 @@ impl HitRecord {
         };
 
         HitRecord {
-            p: self.p,
             normal,
-            t: self.t,
             front_face,
-            mat_ptr: Arc::clone(&self.mat_ptr),
+            ..self
         }
     }
 }
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    /// Box enclosing the object, or `None` if it is unbounded (and can't go in a BVH).
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Density (per unit solid angle) with which `random_direction(origin, time, ..)`
+    /// returns `dir`; zero for objects that can't be sampled (so can't be used as lights).
+    fn pdf_value(&self, _origin: Point3, _dir: Vec3, _time: f64) -> f64 {
+        0.0
+    }
+
+    /// A random unit vector from `origin` towards a point on the object as it is at
+    /// `time`.
+    fn random_direction(&self, _origin: Point3, _time: f64, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+/// Lets shared objects (e.g. ones instanced with `transform::Transformed`) be added to a
+/// `HitList` directly as well.
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        (**self).pdf_value(origin, dir, time)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        (**self).random_direction(origin, time, sampler)
+    }
 }
 
 pub struct Sphere {
@@ -104,57 +218,269 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.orig - self.center;
-        let a = dot(ray.dir, ray.dir);
-        let half_b = dot(oc, ray.dir);
-        let c = dot(oc, oc) - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0.0 {
-            return None;
+        hit_sphere(self.center, self.radius, &self.mat_ptr, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_bounding_box(self.center, self.radius))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        if self
+            .hit(&Ray::with_time(origin, dir, time), 0.001, INF)
+            .is_none()
+        {
+            return 0.0;
         }
+        sphere_pdf(self.center, self.radius, origin)
+    }
 
-        let sqrtd = f64::sqrt(discriminant);
+    fn random_direction(&self, origin: Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        sphere_direction(self.center, self.radius, origin, sampler)
+    }
+}
 
-        // try first root.. does it fall in time range?
-        let mut root = (-half_b - sqrtd) / a;
-        if root < t_min || t_max < root {
-            // try 2nd root
-            root = (-half_b + sqrtd) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
+// Density of `sphere_direction` picking a direction that hits the sphere.
+fn sphere_pdf(center: Point3, radius: f64, origin: Point3) -> f64 {
+    match sphere_cone(center, radius, origin) {
+        Some(cos_theta_max) => uniform_cone_pdf(cos_theta_max),
+        None => uniform_sphere_pdf(),
+    }
+}
+
+// Only the directions that can see the sphere: the cone it subtends from outside, or
+// every direction from inside.
+fn sphere_direction(
+    center: Point3,
+    radius: f64,
+    origin: Point3,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    match sphere_cone(center, radius, origin) {
+        Some(cos_theta_max) => {
+            let local = uniform_cone(sampler.get_2d(), cos_theta_max);
+            Onb::from_w(center - origin).local(local)
         }
+        None => uniform_sphere(sampler.get_2d()),
+    }
+}
 
-        let t = root;
-        let p = ray.at(t);
-        let hr = HitRecord {
-            t,
-            p,
-            normal: (p - self.center) / self.radius,
-            front_face: false,
-            mat_ptr: Arc::clone(&self.mat_ptr),
-        };
-        let outward_normal = (p - self.center) / self.radius;
+/// Cosine of the half-angle of the cone a sphere subtends from `origin`, or `None` if
+/// `origin` is inside it.
+fn sphere_cone(center: Point3, radius: f64, origin: Point3) -> Option<f64> {
+    let to_center = center - origin;
+    let distance_squared = dot(to_center, to_center);
+    let radius_squared = radius * radius;
+    if distance_squared <= radius_squared {
+        return None;
+    }
+    Some((1.0 - radius_squared / distance_squared).sqrt())
+}
+
+/// A sphere whose center moves in a straight line from `center0` at `time0` to
+/// `center1` at `time1`, staying put before and after.
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(
+        (center0, time0): (Point3, f64),
+        (center1, time1): (Point3, f64),
+        radius: f64,
+        mat_ptr: Arc<dyn Material>,
+    ) -> Self {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            mat_ptr,
+        }
+    }
 
-        Some(HitRecord::with_face_normal(hr, ray, outward_normal))
+    pub fn center(&self, time: f64) -> Point3 {
+        if self.time1 == self.time0 {
+            return self.center0;
+        }
+        let s = clamp((time - self.time0) / (self.time1 - self.time0), 0.0, 1.0);
+        self.center0 + (self.center1 - self.center0) * s
     }
 }
 
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let center = self.center(ray.time);
+        hit_sphere(center, self.radius, &self.mat_ptr, ray, t_min, t_max)
+    }
+
+    /// Covers the sphere's whole path between `time0` and `time1`.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let start = sphere_bounding_box(self.center0, self.radius);
+        let end = sphere_bounding_box(self.center1, self.radius);
+        Some(start.surrounding(end))
+    }
+
+    /// Samples the sphere where it is at `time`.
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        if self
+            .hit(&Ray::with_time(origin, dir, time), 0.001, INF)
+            .is_none()
+        {
+            return 0.0;
+        }
+        sphere_pdf(self.center(time), self.radius, origin)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        sphere_direction(self.center(time), self.radius, origin, sampler)
+    }
+}
+
+fn hit_sphere(
+    center: Point3,
+    radius: f64,
+    mat_ptr: &Arc<dyn Material>,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord> {
+    let oc = ray.orig - center;
+    let a = dot(ray.dir, ray.dir);
+    let half_b = dot(oc, ray.dir);
+    let c = dot(oc, oc) - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let sqrtd = f64::sqrt(discriminant);
+
+    // try first root.. does it fall in time range?
+    let mut root = (-half_b - sqrtd) / a;
+    if root < t_min || t_max < root {
+        // try 2nd root
+        root = (-half_b + sqrtd) / a;
+        if root < t_min || t_max < root {
+            return None;
+        }
+    }
+
+    let t = root;
+    let p = ray.at(t);
+    let outward_normal = (p - center) / radius;
+    let (u, v) = sphere_uv((p - center) / radius.abs());
+    let hr = HitRecord {
+        t,
+        p,
+        normal: outward_normal,
+        u,
+        v,
+        front_face: false,
+        mat_ptr: Arc::clone(mat_ptr),
+        object_id: 0,
+        material_id: mat_ptr.id(),
+    };
+
+    Some(HitRecord::with_face_normal(hr, ray, outward_normal))
+}
+
+fn sphere_bounding_box(center: Point3, radius: f64) -> Aabb {
+    // radius may be negative (hollow glass spheres), so use its magnitude
+    let r = Vec3::new(radius, radius, radius) * radius.signum();
+    Aabb::new(center - r, center + r)
+}
+
+/// Maps a point on the unit sphere to (u, v) in [0, 1]: u goes around the y axis
+/// starting from -x, and v goes from the bottom (y = -1) to the top (y = 1).
+pub fn sphere_uv(p: Point3) -> (f64, f64) {
+    let theta = f64::acos(clamp(-p.y(), -1.0, 1.0));
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+#[derive(Default)]
 pub struct HitList {
     objects: Vec<Box<dyn Hittable>>,
+    // the object ID of each one, counting from 1; parts of one object share theirs
+    ids: Vec<u32>,
 }
 
 impl HitList {
     pub fn new() -> HitList {
         HitList {
             objects: Vec::new(),
+            ids: Vec::new(),
         }
     }
-    pub fn clear(mut self: Self) {
+    pub fn clear(&mut self) {
         self.objects.clear();
+        self.ids.clear();
     }
-    pub fn add(self: &mut Self, obj: Box<dyn Hittable>) {
+    pub fn add(&mut self, obj: Box<dyn Hittable>) {
+        let id = self.next_id();
         self.objects.push(obj);
+        self.ids.push(id);
+    }
+
+    /// Adds `parts` (e.g. a mesh's triangles) as one object, as far as object IDs go,
+    /// while still letting a BVH split them up.
+    pub fn add_parts(&mut self, parts: HitList) {
+        let id = self.next_id();
+        self.ids.extend(parts.objects.iter().map(|_| id));
+        self.objects.extend(parts.objects);
+    }
+
+    fn next_id(&self) -> u32 {
+        self.ids.last().map_or(1, |id| id + 1)
+    }
+
+    /// Tags every hit with the ID of the object hit, counting from 1 in the order objects
+    /// were added, for object ID buffers.
+    pub fn with_object_ids(self) -> HitList {
+        let objects = self
+            .objects
+            .into_iter()
+            .zip(&self.ids)
+            .map(|(object, &id)| Box::new(Tagged { id, object }) as Box<dyn Hittable>)
+            .collect();
+        HitList {
+            objects,
+            ids: self.ids,
+        }
+    }
+}
+
+struct Tagged {
+    id: u32,
+    object: Box<dyn Hittable>,
+}
+
+impl Hittable for Tagged {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = self.object.hit(r, t_min, t_max)?;
+        Some(HitRecord {
+            object_id: self.id,
+            ..rec
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        self.object.pdf_value(origin, dir, time)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        self.object.random_direction(origin, time, sampler)
     }
 }
 
@@ -175,7 +501,35 @@ impl Hittable for HitList {
             }
         });
 
-        closest.unwrap()
+        closest.flatten()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.objects.iter().map(|obj| obj.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, bbox| Some(acc.surrounding(bbox?)))
+    }
+
+    /// Samples each object equally often.
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|obj| obj.pdf_value(origin, dir, time))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let n = self.objects.len();
+        if n == 0 {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let i = ((sampler.get_1d() * n as f64) as usize).min(n - 1);
+        self.objects[i].random_direction(origin, time, sampler)
     }
 }
 
@@ -187,8 +541,10 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
+    // rays are fired at random times between these, for motion blur
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -224,17 +580,44 @@ impl Camera {
             vertical,
             u,
             v,
-            w,
             lens_radius,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+
+    /// Keeps the shutter open from time `open` to `close`, so anything moving in that
+    /// interval is blurred.
+    pub fn with_shutter(self, open: f64, close: f64) -> Camera {
+        Camera {
+            shutter_open: open,
+            shutter_close: close,
+            ..self
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::new_random_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
-        Ray::new(
+    /// The direction the camera looks in.
+    pub fn forward(&self) -> Vec3 {
+        self.v.cross(self.u)
+    }
+
+    /// The ray through `(s, t)` on the viewport (from `(0, 0)` at the bottom left to
+    /// `(1, 1)` at the top right), from a point on the lens and a time picked by
+    /// `sampler`.
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let (x, y) = concentric_disk(sampler.get_2d());
+        let offset = (self.u * x + self.v * y) * self.lens_radius;
+        // only draw a random time if there's an interval, so still images stay the same
+        let time = if self.shutter_close > self.shutter_open {
+            let u = sampler.get_1d();
+            self.shutter_open * (1.0 - u) + self.shutter_close * u
+        } else {
+            self.shutter_open
+        };
+        Ray::with_time(
             self.origin + offset,
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
+            time,
         )
     }
 }
@@ -247,3 +630,33 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     }
     x
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLight;
+    use crate::sampler::IndependentSampler;
+    use crate::util::seed_rng;
+
+    #[test]
+    fn test_sphere_sampling_pdf() {
+        // a unit sphere 2 units away subtends a cone with a 30 degree half-angle
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            1.0,
+            Arc::new(DiffuseLight::new(COLOR_WHITE)),
+        );
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let expected = 1.0 / (2.0 * PI * (1.0 - 0.75f64.sqrt()));
+        seed_rng(2);
+        for _ in 0..100 {
+            let dir = sphere.random_direction(origin, 0.0, &mut IndependentSampler);
+            assert!(sphere.hit(&Ray::new(origin, dir), 0.001, INF).is_some());
+            assert!((sphere.pdf_value(origin, dir, 0.0) - expected).abs() < 1e-9);
+        }
+        assert_eq!(sphere.pdf_value(origin, Vec3::new(0.0, 1.0, 0.0), 0.0), 0.0);
+        // from inside, every direction hits it
+        let inside = Point3::new(0.0, 0.0, -2.5);
+        let dir = sphere.random_direction(inside, 0.0, &mut IndependentSampler);
+        assert_eq!(sphere.pdf_value(inside, dir, 0.0), 1.0 / (4.0 * PI));
+    }
+}
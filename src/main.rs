@@ -1,102 +1,252 @@
 use rayon::prelude::*;
-use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::process;
+use std::sync::Arc;
 
+mod cli;
 mod scene;
 
-use rand::Rng;
-use rt::vec3::{Color, Point3, Vec3, COLOR_BLACK};
-use rt::Camera;
+use clap::Parser;
+use rt::adaptive::{heatmap_color, AdaptiveSampling, PixelStats};
+use rt::aov::{aov_path, Aov, AovBuffers, AovSample, AOV_SAMPLES};
+use rt::bvh::BvhNode;
+use rt::environment::EnvironmentMap;
+use rt::film::Film;
+use rt::image_io::{save_image, ImageFormat};
+use rt::integrator::Scene;
+use rt::sampler::Sampler;
+use rt::scene_file::{load_scene, RenderSettings, SceneFile};
+use rt::util::{seed_rng, stream_seed};
+use rt::vec3::Color;
+use rt::Background;
 
 fn main() {
-    let mut max_depth: i32 = 50;
-    let mut samples_per_pixel = 500.0;
-    let mut world = scene::simple_scene();
-    if env::var("FAST_MODE").is_ok() {
-        max_depth = 3;
-        samples_per_pixel = 40.0;
-    }
-    if env::var("DETAIL_MODE").is_ok() {
-        max_depth = 50;
-        samples_per_pixel = 500.0;
+    let args = cli::Args::parse();
+
+    // Built-in scenes are generated randomly, so seed that too.
+    seed_rng(args.seed.unwrap_or(RenderSettings::default().seed));
+    let scene_name = args.scene();
+    let mut scene = match scene::builtin(&scene_name) {
+        Some(scene) => scene,
+        None => match load_scene(&scene_name) {
+            Ok(scene) => scene,
+            Err(err) => {
+                eprintln!("error: {err}");
+                process::exit(1);
+            }
+        },
+    };
+    cli::apply_deprecated_env(&mut scene.render);
+    args.apply(&mut scene);
+    if let Some(path) = &args.environment {
+        let (rotation, intensity) = (args.environment_rotation, args.environment_intensity);
+        match EnvironmentMap::load(path, rotation, intensity) {
+            Ok(map) => scene.background = Background::Environment(Arc::new(map)),
+            Err(err) => {
+                eprintln!("error: {}: {err}", path.display());
+                process::exit(1);
+            }
+        }
     }
-    if env::var("RANDOM_SCENE").is_ok() {
-        world = scene::random_scene();
+    let SceneFile {
+        render: settings,
+        camera: camera_settings,
+        background,
+        world,
+        lights,
+    } = scene;
+
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads as usize)
+            .build_global()
+            .expect("thread pool is only configured once");
     }
 
-    // Image
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width: i64 = 1200;
-    let image_height: i64 = (image_width as f64 / aspect_ratio) as i64;
+    let aovs = settings.aovs.clone();
+    let world = if aovs.contains(&Aov::ObjectId) {
+        world.with_object_ids()
+    } else {
+        world
+    };
+    let world = BvhNode::new(world);
 
-    let max_depth = max_depth;
+    // Image
+    let image_width = settings.image_width as i64;
+    let image_height = settings.image_height as i64;
+    let integrator = args.integrator(&settings);
+    let scene = Scene {
+        world: &world,
+        lights: &lights,
+        background: &background,
+    };
+    let seed = settings.seed;
+    let sampler_kind = settings.sampler;
+    let tone_mapping = settings.tone_mapping;
 
     // Camera
+    let camera = camera_settings.build(settings.aspect_ratio());
 
-    let lookfrom = Point3::new(13.0, 2.0, 3.0);
-    let lookat = Point3::new(0.0, 0.0, 0.0);
-    let dist_to_focus = 10.0;
-    let aperture = 0.1;
-
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        Vec3::new(0.0, 1.0, 0.0),
-        20.0,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-    );
+    let format = match args.image_format() {
+        Ok(format) => format,
+        Err(err) => {
+            eprintln!("error: {err}");
+            process::exit(2);
+        }
+    };
+    let exr = matches!(format, ImageFormat::Exr | ImageFormat::ExrFloat);
+    if !aovs.is_empty() && !exr && args.output.is_none() {
+        eprintln!("error: AOVs need --output, or an EXR format to write them as layers");
+        process::exit(2);
+    }
+    let heatmap_format = args.heatmap.as_ref().map(|path| {
+        ImageFormat::from_path(path).unwrap_or_else(|| {
+            let path = path.display();
+            eprintln!("error: can't tell the image format of `{path}` from its extension");
+            process::exit(2);
+        })
+    });
+    // Opened before rendering, so a bad path doesn't waste a render.
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("error: {}: {err}", path.display());
+                process::exit(1);
+            }
+        },
+        None => Box::new(io::stdout().lock()),
+    };
 
     // Render
+    // The ray for a sample of pixel `(i, j)` (counting up from the bottom left).
+    let camera_ray = |i: i64, j: i64, sampler: &mut dyn Sampler| {
+        let (dx, dy) = sampler.get_2d();
+        let u = (i as f64 + dx) / image_width as f64; // how horizontal? (0 to 1)
+        let v = (j as f64 + dy) / image_height as f64; // how vertical? (0 to 1)
+        camera.get_ray(u, v, sampler)
+    };
+    // The seed of pixel `(i, j)` and a sampler for its samples from `first` on, the same
+    // for every pass and batch.
+    let pixel_sampler = |i: i64, j: i64, first: u32| {
+        let pixel_seed = stream_seed(seed, (j * image_width + i) as u64);
+        let sampler = sampler_kind.start_pixel(settings.samples_per_pixel, pixel_seed, first);
+        (pixel_seed, sampler)
+    };
+    // Adds samples `indices` of pixel `(i, j)` (counting up from the bottom left) to `stats`.
+    let render_samples = |i: i64, j: i64, indices: Range<u32>, stats: &mut PixelStats| {
+        let (pixel_seed, mut sampler) = pixel_sampler(i, j, indices.start);
+        for index in indices {
+            sampler.start_pixel_sample(pixel_seed, index);
+            let ray = camera_ray(i, j, sampler.as_mut());
+            stats.add(integrator.li(&ray, &scene, sampler.as_mut()));
+        }
+    };
+    // in output order, from the top left
+    let stats: Vec<PixelStats> = match settings.adaptive_threshold {
+        Some(threshold) => {
+            let adaptive = AdaptiveSampling::new(threshold, settings.samples_per_pixel);
+            let pixels = (image_width * image_height) as usize;
+            adaptive.render(
+                pixels,
+                settings.samples_per_pixel,
+                |pixel, indices, stats| {
+                    let (i, row) = (pixel as i64 % image_width, pixel as i64 / image_width);
+                    render_samples(i, image_height - 1 - row, indices, stats);
+                },
+                |round, noisy| eprintln!("Adaptive round {round}: {noisy} noisy pixels..."),
+            )
+        }
+        None => {
+            let mut stats = Vec::with_capacity((image_width * image_height) as usize);
+            for j in (0..image_height).rev() {
+                eprintln!("Lines remaining: {j}...");
+                let row: Vec<PixelStats> = (0..image_width)
+                    .into_par_iter()
+                    .map(|i| {
+                        let mut pixel_stats = PixelStats::default();
+                        render_samples(i, j, 0..settings.samples_per_pixel, &mut pixel_stats);
+                        pixel_stats
+                    })
+                    .collect();
+                stats.extend(row);
+            }
+            stats
+        }
+    };
 
-    // colors are in ascii
-    println!("P3");
-
-    // columns, rows
-    println!("{} {}", image_width, image_height);
+    let (width, height) = (settings.image_width, settings.image_height);
+    let mut film = Film::new(width, height);
+    for (pixel, pixel_stats) in stats.iter().enumerate() {
+        let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+        film.add_samples(x, y, pixel_stats.sum(), pixel_stats.count());
+    }
 
-    // max color
-    println!("255");
+    // The AOVs see the same camera rays as each pixel's first samples.
+    let aov_buffers = (!aovs.is_empty()).then(|| {
+        eprintln!("Gathering AOVs...");
+        let samples = settings.samples_per_pixel.min(AOV_SAMPLES);
+        let forward = camera.forward();
+        AovBuffers::render(width, height, |x, row| {
+            let (i, j) = (x as i64, image_height - 1 - row as i64);
+            let (pixel_seed, mut sampler) = pixel_sampler(i, j, 0);
+            (0..samples)
+                .map(|index| {
+                    sampler.start_pixel_sample(pixel_seed, index);
+                    let ray = camera_ray(i, j, sampler.as_mut());
+                    AovSample::trace(&ray, forward, &scene, sampler.as_mut())
+                })
+                .collect()
+        })
+    });
+
+    // with the AOVs as extra layers, if it's an EXR image
+    let write = |out: &mut dyn Write| {
+        let mut out = BufWriter::new(out);
+        match &aov_buffers {
+            Some(buffers) if exr => {
+                let half = format == ImageFormat::Exr;
+                buffers.write_exr(&mut out, &film, &aovs, half)?;
+            }
+            _ => film.write(&mut out, format, &tone_mapping)?,
+        }
+        out.flush()
+    };
+    let written = write(&mut output).map_err(|err| match &args.output {
+        Some(path) => format!("{}: {err}", path.display()),
+        None => err.to_string(),
+    });
+    if let Err(err) = written {
+        eprintln!("error: writing image: {err}");
+        process::exit(1);
+    }
+    if let (Some(buffers), Some(path), false) = (&aov_buffers, &args.output, exr) {
+        for &aov in &aovs {
+            let aov_path = aov_path(path, aov);
+            if let Err(err) = buffers.save(aov, &aov_path, format) {
+                eprintln!(
+                    "error: writing {} AOV: {}: {err}",
+                    aov.name(),
+                    aov_path.display()
+                );
+                process::exit(1);
+            }
+        }
+    }
 
-    // RGB triplets
-    for j in (0..image_height).rev() {
-        eprintln!("Lines remaining: {j}...");
-        let out: Vec<Color> = (0..image_width)
-            .into_par_iter()
-            .map(|i| {
-                let mut pixel_color = COLOR_BLACK;
-                for _ in 0..samples_per_pixel as i64 {
-                    let mut rng = rand::thread_rng();
-                    let u = (i as f64 + rng.gen::<f64>()) / (image_width as f64 - 1.0); // how horizontal? (0 to 1)
-                    let v = (j as f64 + rng.gen::<f64>()) / (image_height as f64 - 1.0); // how vertical? (0 to 1)
-                    let ray = camera.get_ray(u, v);
-                    pixel_color = pixel_color + ray.color(&world, max_depth);
-                }
-                pixel_color
-                // }
-            })
+    if let (Some(path), Some(format)) = (&args.heatmap, heatmap_format) {
+        let max = stats.iter().map(PixelStats::count).max().unwrap_or(0);
+        eprintln!("Most samples in one pixel: {max}");
+        let heatmap: Vec<Color> = stats
+            .iter()
+            .map(|pixel| heatmap_color(pixel.count(), max))
             .collect();
-        for pixel_color in out {
-            write_color(pixel_color, samples_per_pixel);
+        if let Err(err) = save_image(path, format, width, height, &heatmap) {
+            eprintln!("error: writing heatmap: {}: {err}", path.display());
+            process::exit(1);
         }
     }
     eprintln!("Done.");
 }
-
-fn write_color(color: Color, samples_per_pixel: f64) {
-    let mut r = color.x();
-    let mut g = color.y();
-    let mut b = color.z();
-
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let scale = 1.0 / samples_per_pixel;
-    r = f64::sqrt(scale * r);
-    g = f64::sqrt(scale * g);
-    b = f64::sqrt(scale * b);
-
-    let ir = (256.0 * rt::clamp(r, 0.0, 0.999)) as i64;
-    let ig = (256.0 * rt::clamp(g, 0.0, 0.999)) as i64;
-    let ib = (256.0 * rt::clamp(b, 0.0, 0.999)) as i64;
-
-    println!("{ir} {ig} {ib}");
-}
@@ -1,65 +1,176 @@
-use crate::{dot, util::random_double, Color, HitRecord, Ray, Vec3, COLOR_WHITE};
+use std::sync::Arc;
+
+use crate::sampler::Sampler;
+use crate::sampling::{
+    cosine_hemisphere, cosine_hemisphere_pdf, uniform_sphere, uniform_sphere_pdf, Onb,
+};
+use crate::texture::{SolidColor, Texture};
+use crate::{dot, Color, HitRecord, Ray, Vec3, COLOR_BLACK, COLOR_WHITE};
 
 pub struct ScatterResult {
     pub scattered: Ray,
+    /// The BSDF times the cosine term, divided by `pdf`.
     pub attenuation: Color,
+    /// Density (per unit solid angle) `scattered` was chosen with, or `None` if the
+    /// material could only have scattered that way (e.g. a mirror), which means light
+    /// sampling can't help.
+    pub pdf: Option<f64>,
 }
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterResult>;
+    fn scatter(&self, r: &Ray, rec: &HitRecord, sampler: &mut dyn Sampler)
+        -> Option<ScatterResult>;
+
+    /// Density with which `scatter` would send the ray off in `dir`. Only needed by
+    /// materials whose `ScatterResult::pdf` isn't `None`.
+    fn scattering_pdf(&self, _r: &Ray, _rec: &HitRecord, _dir: Vec3) -> f64 {
+        0.0
+    }
+
+    /// How much of the light arriving from `dir` is scattered back along the ray: the
+    /// BSDF times the cosine term. Used for light sampling, so like `scattering_pdf`
+    /// only needed by non-specular materials.
+    fn eval(&self, _r: &Ray, _rec: &HitRecord, _dir: Vec3) -> Color {
+        COLOR_BLACK
+    }
+
+    /// Light given off by the surface itself. Most materials don't emit anything.
+    fn emitted(&self, _r: &Ray, _rec: &HitRecord) -> Color {
+        COLOR_BLACK
+    }
+
+    /// The material's ID in material ID buffers, once numbered by `MaterialIds`; 0
+    /// otherwise.
+    fn id(&self) -> u32 {
+        0
+    }
+}
+
+/// Numbers materials for material ID buffers, counting from 1 in the order they're
+/// tagged, so the same scene gets the same IDs on every render.
+#[derive(Default)]
+pub struct MaterialIds {
+    count: u32,
+}
+
+impl MaterialIds {
+    /// `material`, tagged with the next ID.
+    pub fn tag(&mut self, material: Arc<dyn Material>) -> Arc<dyn Material> {
+        self.count += 1;
+        Arc::new(Tagged {
+            id: self.count,
+            material,
+        })
+    }
+}
+
+struct Tagged {
+    id: u32,
+    material: Arc<dyn Material>,
+}
+
+impl Material for Tagged {
+    fn scatter(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        self.material.scatter(r, rec, sampler)
+    }
+
+    fn scattering_pdf(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        self.material.scattering_pdf(r, rec, dir)
+    }
+
+    fn eval(&self, r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        self.material.eval(r, rec, dir)
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord) -> Color {
+        self.material.emitted(r, rec)
+    }
+
+    fn id(&self) -> u32 {
+        self.id
+    }
 }
 
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _r: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         // eprintln!("scatter Lambertian");
-        let random_scatter_direction = rec.normal + Vec3::new_random_unit_vector();
-        let scatter_direction = if random_scatter_direction.near_zero() {
-            rec.normal
-        } else {
-            random_scatter_direction
-        };
-
+        let local = cosine_hemisphere(sampler.get_2d());
+        let scatter_direction = Onb::from_w(rec.normal).local(local);
         Some(ScatterResult {
-            scattered: Ray::new(rec.p, scatter_direction),
-            attenuation: self.albedo,
+            scattered: Ray::with_time(rec.p, scatter_direction, r.time),
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: Some(cosine_hemisphere_pdf(local.z())),
         })
     }
+
+    fn scattering_pdf(&self, _r: &Ray, rec: &HitRecord, dir: Vec3) -> f64 {
+        cosine_hemisphere_pdf(dot(rec.normal, dir.unit_vector()))
+    }
+
+    fn eval(&self, _r: &Ray, rec: &HitRecord, dir: Vec3) -> Color {
+        let pdf = cosine_hemisphere_pdf(dot(rec.normal, dir.unit_vector()));
+        self.albedo.value(rec.u, rec.v, rec.p) * pdf
+    }
 }
 
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>, fuzz: f64) -> Self {
         Self { albedo, fuzz }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         // eprintln!("scatter Metal");
         let reflected = reflect(r.dir.unit_vector(), rec.normal);
 
-        let scattered = Ray::new(
-            rec.p,
-            reflected + Vec3::new_random_in_unit_sphere() * self.fuzz,
-        );
+        // a uniform point in the unit ball: a direction and a distance
+        let in_ball = uniform_sphere(sampler.get_2d()) * sampler.get_1d().cbrt();
+        let scattered = Ray::with_time(rec.p, reflected + in_ball * self.fuzz, r.time);
         if dot(scattered.dir, rec.normal) > 0.0 {
+            // treated as a perfect mirror, even when fuzzy
             Some(ScatterResult {
                 scattered,
-                attenuation: self.albedo,
+                attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+                pdf: None,
             })
         } else {
             None
@@ -84,7 +195,12 @@ impl Dialectric {
 }
 
 impl Material for Dialectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterResult> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
         let refraction_ratio = if rec.front_face {
             1.0 / self.index_of_refraction
         } else {
@@ -99,16 +215,17 @@ impl Material for Dialectric {
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
 
         let direction =
-            if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_double() {
+            if cannot_refract || reflectance(cos_theta, refraction_ratio) > sampler.get_1d() {
                 reflect(unit_direction, rec.normal)
             } else {
                 refract(unit_direction, rec.normal, refraction_ratio)
             };
 
-        let scattered = Ray::new(rec.p, direction);
+        let scattered = Ray::with_time(rec.p, direction, r_in.time);
         Some(ScatterResult {
             scattered,
             attenuation: COLOR_WHITE,
+            pdf: None,
         })
     }
 }
@@ -124,7 +241,75 @@ fn reflectance(cos_theta: f64, refraction_ratio: f64) -> f64 {
     // Use Schlick's approximation for reflectance.
     let r0 = (1.0 - refraction_ratio) / (1.0 + refraction_ratio);
     let r0 = r0 * r0;
-    return r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
+    r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
+}
+
+/// A light source: emits `emit` from both sides and absorbs everything that hits it.
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn with_texture(emit: Arc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _r: &Ray,
+        _rec: &HitRecord,
+        _sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        None
+    }
+
+    fn emitted(&self, _r: &Ray, rec: &HitRecord) -> Color {
+        self.emit.value(rec.u, rec.v, rec.p)
+    }
+}
+
+/// Scatters light equally in every direction; the phase function of a `ConstantMedium`.
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Self::with_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn with_texture(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<ScatterResult> {
+        Some(ScatterResult {
+            scattered: Ray::with_time(rec.p, uniform_sphere(sampler.get_2d()), r.time),
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: Some(uniform_sphere_pdf()),
+        })
+    }
+
+    fn scattering_pdf(&self, _r: &Ray, _rec: &HitRecord, _dir: Vec3) -> f64 {
+        uniform_sphere_pdf()
+    }
+
+    fn eval(&self, _r: &Ray, rec: &HitRecord, _dir: Vec3) -> Color {
+        self.albedo.value(rec.u, rec.v, rec.p) * uniform_sphere_pdf()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::util::random_double;
+use crate::vec3::Vec3;
+use crate::{HitRecord, Hittable, Ray, INF};
+
+/// A volume of uniform density (fog, smoke) filling a closed `boundary` shape.
+///
+/// A ray passing through scatters at an exponentially distributed distance, so the
+/// further it travels inside, the more likely it is to scatter; the material (usually
+/// `Isotropic`) decides where it goes from there. The boundary must be convex, since
+/// only the first stretch of the ray inside it counts.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Box<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Where the ray enters and leaves the boundary, even if it starts inside.
+        let entry = self.boundary.hit(r, -INF, INF)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, INF)?;
+
+        let t_enter = entry.t.max(t_min).max(0.0);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_length = r.dir.length();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * random_double().ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        Some(HitRecord {
+            p: r.at(t),
+            // arbitrary, since an isotropic medium has no surface
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            t,
+            u: 0.0,
+            v: 0.0,
+            front_face: true,
+            mat_ptr: Arc::clone(&self.phase_function),
+            object_id: 0,
+            material_id: self.phase_function.id(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Isotropic;
+    use crate::quad::Cuboid;
+    use crate::util::seed_rng;
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn test_scatter_distance_is_exponential() {
+        let phase: Arc<dyn Material> = Arc::new(Isotropic::new(Color::new(1.0, 1.0, 1.0)));
+        // 10 units deep, so with density 0.1 a ray gets through e^-1 of the time
+        let boundary = Cuboid::new(
+            Point3::new(-5.0, -5.0, -5.0),
+            Point3::new(5.0, 5.0, 5.0),
+            Arc::clone(&phase),
+        );
+        let medium = ConstantMedium::new(Box::new(boundary), 0.1, phase);
+        let r = Ray::new(Point3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -2.0));
+
+        seed_rng(1);
+        let n = 10_000;
+        let mut passed = 0;
+        for _ in 0..n {
+            match medium.hit(&r, 0.001, INF) {
+                Some(rec) => assert!((2.5..=7.5).contains(&rec.t), "{}", rec.t),
+                None => passed += 1,
+            }
+        }
+        let expected = (-1.0f64).exp();
+        assert!((passed as f64 / n as f64 - expected).abs() < 0.02);
+
+        // a ray that stops short of the medium can't scatter in it
+        assert!(medium.hit(&r, 0.001, 2.0).is_none());
+    }
+}
@@ -0,0 +1,571 @@
+//! Loader for Wavefront `.obj` geometry and its companion `.mtl` material libraries.
+//!
+//! Supports `v`/`vt`/`vn`/`f` statements (including negative, relative indices),
+//! fan-triangulates polygons, splits the model into groups on `g`/`o`/`usemtl`, and maps
+//! MTL materials onto `Lambertian`, `Metal` and `Dialectric`. Statements we don't render
+//! (smoothing groups, lines, points, texture maps...) are skipped.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::material::{Dialectric, Lambertian, Material, MaterialIds, Metal};
+use crate::triangle::{MeshFace, TriangleMesh};
+use crate::vec3::{Color, Point3, Vec3};
+use crate::HitList;
+
+/// A malformed statement, with the 1-based line it appeared on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, error: ParseError },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, error } => {
+                write!(f, "{}:{}: {}", path.display(), error.line, error.message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { error, .. } => Some(error),
+        }
+    }
+}
+
+/// A material from an `.mtl` file, keeping the raw values it was described with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub name: String,
+    /// `Kd`
+    pub diffuse: Color,
+    /// `Ks`
+    pub specular: Color,
+    /// `Ns`, the Phong exponent
+    pub shininess: f64,
+    /// `Ni`
+    pub index_of_refraction: f64,
+    /// `d`, or `1 - Tr`
+    pub dissolve: f64,
+    /// `illum`
+    pub illum: u32,
+}
+
+impl MtlMaterial {
+    fn new(name: &str) -> Self {
+        MtlMaterial {
+            name: name.to_string(),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            index_of_refraction: 1.5,
+            dissolve: 1.0,
+            illum: 1,
+        }
+    }
+
+    /// Picks the closest of our materials:
+    /// - transparent (`d < 1`) or refractive illumination models become `Dialectric`,
+    /// - reflective illumination models become `Metal`, with fuzz derived from `Ns`,
+    /// - everything else is a `Lambertian` with the diffuse color.
+    pub fn to_material(&self) -> Arc<dyn Material> {
+        match self.illum {
+            _ if self.dissolve < 1.0 => Arc::new(Dialectric::new(self.index_of_refraction)),
+            4 | 6 | 7 | 9 => Arc::new(Dialectric::new(self.index_of_refraction)),
+            3 | 5 | 8 => {
+                // Map the Phong exponent onto a roughness: Ns=0 is fully fuzzy, high Ns is a mirror.
+                let fuzz = f64::sqrt(2.0 / (self.shininess + 2.0));
+                Arc::new(Metal::new(self.specular, fuzz))
+            }
+            _ => Arc::new(Lambertian::new(self.diffuse)),
+        }
+    }
+}
+
+/// Faces sharing a group name and material.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Option<String>,
+    pub faces: Vec<MeshFace>,
+}
+
+/// Parsed contents of an `.obj` file (plus any materials it pulled in).
+#[derive(Debug, Default)]
+pub struct ObjModel {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub groups: Vec<ObjGroup>,
+    pub material_libs: Vec<String>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+impl ObjModel {
+    /// One `TriangleMesh` per group. Groups using an unknown (or no) material get
+    /// `default_material`. The `.mtl` materials are tagged with `material_ids` in the order
+    /// groups first use them.
+    pub fn meshes(
+        &self,
+        default_material: Arc<dyn Material>,
+        material_ids: &mut MaterialIds,
+    ) -> Vec<TriangleMesh> {
+        let mut converted: HashMap<&str, Arc<dyn Material>> = HashMap::new();
+        self.groups
+            .iter()
+            .filter(|group| !group.faces.is_empty())
+            .map(|group| {
+                let mat_ptr = match group
+                    .material
+                    .as_deref()
+                    .and_then(|name| self.materials.get(name))
+                {
+                    Some(mtl) => Arc::clone(
+                        converted
+                            .entry(mtl.name.as_str())
+                            .or_insert_with(|| material_ids.tag(mtl.to_material())),
+                    ),
+                    None => Arc::clone(&default_material),
+                };
+                self.group_mesh(group, mat_ptr)
+            })
+            .collect()
+    }
+
+    /// All groups' triangles in one list, ready to be put in a `BvhNode`.
+    pub fn to_hitlist(
+        &self,
+        default_material: Arc<dyn Material>,
+        material_ids: &mut MaterialIds,
+    ) -> HitList {
+        let mut list = HitList::new();
+        for mesh in self.meshes(default_material, material_ids) {
+            for triangle in Arc::new(mesh).triangles().objects {
+                list.add(triangle);
+            }
+        }
+        list
+    }
+
+    // Copies just the vertices a group uses into its own buffers.
+    fn group_mesh(&self, group: &ObjGroup, mat_ptr: Arc<dyn Material>) -> TriangleMesh {
+        fn remap<T: Copy>(
+            indices: [usize; 3],
+            source: &[T],
+            used: &mut HashMap<usize, usize>,
+            dest: &mut Vec<T>,
+        ) -> [usize; 3] {
+            indices.map(|i| {
+                *used.entry(i).or_insert_with(|| {
+                    dest.push(source[i]);
+                    dest.len() - 1
+                })
+            })
+        }
+
+        let mut mesh = TriangleMesh::new(Vec::new(), Vec::new(), mat_ptr);
+        let (mut used_positions, mut used_normals, mut used_uvs) =
+            (HashMap::new(), HashMap::new(), HashMap::new());
+        for face in &group.faces {
+            let face = MeshFace {
+                positions: remap(
+                    face.positions,
+                    &self.positions,
+                    &mut used_positions,
+                    &mut mesh.positions,
+                ),
+                normals: face
+                    .normals
+                    .map(|n| remap(n, &self.normals, &mut used_normals, &mut mesh.normals)),
+                uvs: face
+                    .uvs
+                    .map(|uv| remap(uv, &self.uvs, &mut used_uvs, &mut mesh.uvs)),
+            };
+            mesh.faces.push(face);
+        }
+        mesh
+    }
+
+    fn current_group(&mut self) -> &mut ObjGroup {
+        if self.groups.is_empty() {
+            self.groups.push(ObjGroup {
+                name: "default".to_string(),
+                material: None,
+                faces: Vec::new(),
+            });
+        }
+        self.groups.last_mut().unwrap()
+    }
+
+    // Starts a new group, unless the current one is still empty and can just be renamed.
+    fn start_group(&mut self, name: Option<String>, material: Option<Option<String>>) {
+        let current = self.current_group();
+        let name = name.unwrap_or_else(|| current.name.clone());
+        let material = material.unwrap_or_else(|| current.material.clone());
+        if current.faces.is_empty() {
+            current.name = name;
+            current.material = material;
+        } else {
+            self.groups.push(ObjGroup {
+                name,
+                material,
+                faces: Vec::new(),
+            });
+        }
+    }
+}
+
+/// Reads an `.obj` file along with the `.mtl` libraries it references (resolved
+/// relative to the `.obj` file's directory).
+pub fn load_obj(path: impl AsRef<Path>) -> Result<ObjModel, ObjError> {
+    let path = path.as_ref();
+    let source = read(path)?;
+    let mut model = parse_obj(&source).map_err(|error| ObjError::Parse {
+        path: path.to_path_buf(),
+        error,
+    })?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    for lib in &model.material_libs {
+        let lib_path = dir.join(lib);
+        let source = read(&lib_path)?;
+        let materials = parse_mtl(&source).map_err(|error| ObjError::Parse {
+            path: lib_path,
+            error,
+        })?;
+        model.materials.extend(materials);
+    }
+
+    Ok(model)
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Splits a file into `(line number, keyword, arguments)`, skipping blanks and comments.
+fn statements(source: &str) -> impl Iterator<Item = (usize, &str, Vec<&str>)> {
+    source.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let keyword = words.next()?;
+        Some((i + 1, keyword, words.collect()))
+    })
+}
+
+fn parse_floats<const N: usize>(
+    line: usize,
+    keyword: &str,
+    args: &[&str],
+    required: usize,
+    defaults: [f64; N],
+) -> Result<[f64; N], ParseError> {
+    if args.len() < required || args.len() > N {
+        return Err(ParseError {
+            line,
+            message: format!(
+                "`{keyword}` takes {} numbers, found {}",
+                if required == N {
+                    format!("{N}")
+                } else {
+                    format!("{required} to {N}")
+                },
+                args.len()
+            ),
+        });
+    }
+    let mut out = defaults;
+    for (slot, arg) in out.iter_mut().zip(args) {
+        *slot = arg.parse().map_err(|_| ParseError {
+            line,
+            message: format!("`{keyword}`: invalid number `{arg}`"),
+        })?;
+    }
+    Ok(out)
+}
+
+/// Resolves a 1-based (or negative, counting back from the end) OBJ index.
+fn resolve_index(line: usize, raw: &str, count: usize, what: &str) -> Result<usize, ParseError> {
+    let err = |message: String| ParseError { line, message };
+    let index: i64 = raw
+        .parse()
+        .map_err(|_| err(format!("invalid {what} index `{raw}`")))?;
+    let resolved = match index {
+        0 => return Err(err(format!("{what} index can't be 0"))),
+        i if i > 0 => i - 1,
+        i => count as i64 + i,
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(err(format!(
+            "{what} index {index} is out of range ({count} defined so far)"
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_face_vertex(line: usize, raw: &str, model: &ObjModel) -> Result<FaceVertex, ParseError> {
+    let mut parts = raw.split('/');
+    let position = resolve_index(
+        line,
+        parts.next().unwrap_or(""),
+        model.positions.len(),
+        "vertex",
+    )?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        Some(raw) => Some(resolve_index(
+            line,
+            raw,
+            model.uvs.len(),
+            "texture coordinate",
+        )?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(raw) => Some(resolve_index(line, raw, model.normals.len(), "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(ParseError {
+            line,
+            message: format!("malformed face vertex `{raw}`"),
+        });
+    }
+    Ok((position, uv, normal))
+}
+
+/// Parses the contents of an `.obj` file. `mtllib` references are collected in
+/// `material_libs` but not loaded; use `load_obj` for that.
+pub fn parse_obj(source: &str) -> Result<ObjModel, ParseError> {
+    let mut model = ObjModel::default();
+
+    for (line, keyword, args) in statements(source) {
+        match keyword {
+            "v" => {
+                let [x, y, z, _w] = parse_floats(line, keyword, &args, 3, [0.0, 0.0, 0.0, 1.0])?;
+                model.positions.push(Point3::new(x, y, z));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(line, keyword, &args, 3, [0.0; 3])?;
+                model.normals.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                let [u, v, _w] = parse_floats(line, keyword, &args, 1, [0.0; 3])?;
+                model.uvs.push((u, v));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(ParseError {
+                        line,
+                        message: format!("face needs at least 3 vertices, found {}", args.len()),
+                    });
+                }
+                let vertices = args
+                    .iter()
+                    .map(|raw| parse_face_vertex(line, raw, &model))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                // Fan-triangulate around the first vertex.
+                for i in 1..vertices.len() - 1 {
+                    let tri = [vertices[0], vertices[i], vertices[i + 1]];
+                    let uvs = tri.iter().map(|v| v.1).collect::<Option<Vec<_>>>();
+                    let normals = tri.iter().map(|v| v.2).collect::<Option<Vec<_>>>();
+                    model.current_group().faces.push(MeshFace {
+                        positions: tri.map(|v| v.0),
+                        normals: normals.map(|n| [n[0], n[1], n[2]]),
+                        uvs: uvs.map(|uv| [uv[0], uv[1], uv[2]]),
+                    });
+                }
+            }
+            "g" | "o" => {
+                let name = if args.is_empty() {
+                    "default".to_string()
+                } else {
+                    args.join(" ")
+                };
+                model.start_group(Some(name), None);
+            }
+            "usemtl" => {
+                let name = args.first().ok_or_else(|| ParseError {
+                    line,
+                    message: "`usemtl` needs a material name".to_string(),
+                })?;
+                model.start_group(None, Some(Some(name.to_string())));
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(ParseError {
+                        line,
+                        message: "`mtllib` needs a file name".to_string(),
+                    });
+                }
+                model
+                    .material_libs
+                    .extend(args.iter().map(|s| s.to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(model)
+}
+
+/// Parses the contents of an `.mtl` file into materials keyed by name.
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, MtlMaterial>, ParseError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    for (line, keyword, args) in statements(source) {
+        if keyword == "newmtl" {
+            let name = args.first().ok_or_else(|| ParseError {
+                line,
+                message: "`newmtl` needs a material name".to_string(),
+            })?;
+            if let Some(done) = current.replace(MtlMaterial::new(name)) {
+                materials.insert(done.name.clone(), done);
+            }
+            continue;
+        }
+
+        let known = ["Kd", "Ks", "Ns", "Ni", "d", "Tr", "illum"];
+        if !known.contains(&keyword) {
+            continue;
+        }
+        let mtl = current.as_mut().ok_or_else(|| ParseError {
+            line,
+            message: format!("`{keyword}` before any `newmtl`"),
+        })?;
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parse_floats(line, keyword, &args, 3, [0.0; 3])?;
+                mtl.diffuse = Color::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = parse_floats(line, keyword, &args, 3, [0.0; 3])?;
+                mtl.specular = Color::new(r, g, b);
+            }
+            "Ns" => mtl.shininess = parse_floats(line, keyword, &args, 1, [0.0])?[0],
+            "Ni" => mtl.index_of_refraction = parse_floats(line, keyword, &args, 1, [0.0])?[0],
+            "d" => mtl.dissolve = parse_floats(line, keyword, &args, 1, [0.0])?[0],
+            "Tr" => mtl.dissolve = 1.0 - parse_floats(line, keyword, &args, 1, [0.0])?[0],
+            "illum" => {
+                let raw = args.first().copied().unwrap_or("");
+                mtl.illum = raw.parse().map_err(|_| ParseError {
+                    line,
+                    message: format!("`illum`: invalid illumination model `{raw}`"),
+                })?;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if let Some(done) = current {
+        materials.insert(done.name.clone(), done);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_obj_triangulates_and_resolves_indices() {
+        let source = "\
+# a unit quad, then a triangle using relative indices
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+vn 0 0 1
+g quad
+usemtl red
+f 1/1/1 2/1/1 3/2/1 4/2/1
+g tri
+f -4 -3 -1
+";
+        let model = parse_obj(source).unwrap();
+        assert_eq!(model.groups.len(), 2);
+
+        let quad = &model.groups[0];
+        assert_eq!(quad.name, "quad");
+        assert_eq!(quad.material.as_deref(), Some("red"));
+        assert_eq!(quad.faces.len(), 2);
+        assert_eq!(quad.faces[1].positions, [0, 2, 3]);
+        assert_eq!(quad.faces[1].uvs, Some([0, 1, 1]));
+        assert_eq!(quad.faces[1].normals, Some([0, 0, 0]));
+
+        let tri = &model.groups[1];
+        assert_eq!(tri.material.as_deref(), Some("red"));
+        assert_eq!(tri.faces, vec![MeshFace::new([0, 1, 3])]);
+    }
+
+    #[test]
+    fn test_parse_obj_reports_line() {
+        let source = "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n";
+        let err = parse_obj(source).unwrap_err();
+        assert_eq!(err.line, 4);
+        assert_eq!(
+            err.message,
+            "vertex index 3 is out of range (2 defined so far)"
+        );
+
+        let err = parse_obj("v 0 zero 0\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: `v`: invalid number `zero`");
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let source = "\
+newmtl glass
+Ni 1.45
+d 0.1
+illum 4
+
+newmtl steel
+Ks 0.6 0.6 0.6
+Ns 200
+illum 3
+";
+        let materials = parse_mtl(source).unwrap();
+        assert_eq!(materials["glass"].index_of_refraction, 1.45);
+        assert_eq!(materials["glass"].dissolve, 0.1);
+        assert_eq!(materials["steel"].specular, Color::new(0.6, 0.6, 0.6));
+        assert_eq!(materials["steel"].illum, 3);
+
+        let err = parse_mtl("Kd 1 1 1\n").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
@@ -0,0 +1,147 @@
+//! Perlin gradient noise, and fractal sums of it for procedural textures.
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use crate::vec3::{dot, Point3, Vec3};
+
+const POINT_COUNT: usize = 256;
+
+/// Perlin gradient noise: a random unit gradient at each integer lattice point, blended
+/// smoothly (trilinearly, with Hermite weights) in between.
+///
+/// The lattice comes from its own seeded generator rather than the renderer's shared
+/// one, so the same seed always gives the same pattern.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                );
+                let len = v.length();
+                if len > 1e-3 && len <= 1.0 {
+                    break v / len;
+                }
+            })
+            .collect();
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        Self {
+            perm_x: permutation(),
+            perm_y: permutation(),
+            perm_z: permutation(),
+            gradients,
+        }
+    }
+
+    /// Noise at `p`, roughly in `[-1, 1]` and zero at every lattice point.
+    pub fn noise(&self, p: Point3) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (u, v, w) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        let mut corners = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in corners.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    *gradient = self.gradients[self.perm_x[wrap(i + di as i64)]
+                        ^ self.perm_y[wrap(j + dj as i64)]
+                        ^ self.perm_z[wrap(k + dk as i64)]];
+                }
+            }
+        }
+        interpolate(&corners, u, v, w)
+    }
+
+    /// Sum of `octaves` layers of noise, each at twice the frequency and half the
+    /// amplitude of the one before (fractional Brownian motion). Roughly in `[-1, 1]`.
+    pub fn fbm(&self, p: Point3, octaves: u32) -> f64 {
+        self.fractal(p, octaves, |n| n)
+    }
+
+    /// Like `fbm`, but summing the absolute value of each layer, which gives the sharp
+    /// creases of turbulence. In `[0, 1)`.
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+        self.fractal(p, octaves, f64::abs)
+    }
+
+    fn fractal(&self, p: Point3, octaves: u32, layer: fn(f64) -> f64) -> f64 {
+        let mut sum = 0.0;
+        let mut weight = 1.0;
+        let mut total_weight = 0.0;
+        let mut p = p;
+        for _ in 0..octaves.max(1) {
+            sum += weight * layer(self.noise(p));
+            total_weight += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+        sum / total_weight
+    }
+}
+
+fn wrap(i: i64) -> usize {
+    i.rem_euclid(POINT_COUNT as i64) as usize
+}
+
+fn interpolate(corners: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing, so the noise has no visible seams at lattice cells
+    let (uu, vv, ww) = (
+        u * u * (3.0 - 2.0 * u),
+        v * v * (3.0 - 2.0 * v),
+        w * w * (3.0 - 2.0 * w),
+    );
+    let mut sum = 0.0;
+    for (i, plane) in corners.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, &gradient) in row.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let offset = Vec3::new(u - fi, v - fj, w - fk);
+                sum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                    * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                    * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                    * dot(gradient, offset);
+            }
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_is_seeded_and_smooth() {
+        let a = Perlin::new(1);
+        let p = Point3::new(1.3, -2.7, 0.4);
+        assert_eq!(a.noise(p), Perlin::new(1).noise(p));
+        assert_ne!(a.noise(p), Perlin::new(2).noise(p));
+
+        assert_eq!(a.noise(Point3::new(3.0, -1.0, 7.0)), 0.0);
+        let q = p + Vec3::new(1e-6, 0.0, 0.0);
+        assert!((a.noise(p) - a.noise(q)).abs() < 1e-5);
+
+        for i in 0..100 {
+            let p = Point3::new(i as f64 * 0.37, i as f64 * 0.11, -(i as f64) * 0.23);
+            assert!(a.noise(p).abs() <= 1.0);
+            assert!((0.0..1.0).contains(&a.turbulence(p, 7)));
+            assert!(a.fbm(p, 7).abs() <= 1.0);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::sampler::Sampler;
+use crate::vec3::{dot, Point3, Vec3};
+use crate::{HitList, HitRecord, Hittable, Ray, INF};
+
+/// A parallelogram with one corner at `q` and sides `u` and `v`.
+///
+/// Its UVs run from `(0, 0)` at `q` to `(1, 1)` at `q + u + v`, and the outward normal is
+/// `u × v`, so listing the sides counter-clockwise (seen from outside) makes it face you.
+pub struct Quad {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub mat_ptr: Arc<dyn Material>,
+    // unit normal, and `n / (n · n)` for the unnormalized normal `n`, which turns a point
+    // in the plane into its (u, v) coordinates
+    normal: Vec3,
+    w: Vec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, mat_ptr: Arc<dyn Material>) -> Self {
+        let n = u.cross(v);
+        Quad {
+            q,
+            u,
+            v,
+            mat_ptr,
+            normal: n.unit_vector(),
+            w: n / dot(n, n),
+            area: n.length(),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denom = dot(self.normal, r.dir);
+        // ray is parallel to the quad's plane
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = dot(self.normal, self.q - r.orig) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let p = r.at(t);
+        let planar = p - self.q;
+        let alpha = dot(self.w, planar.cross(self.v));
+        let beta = dot(self.w, self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let hr = HitRecord {
+            p,
+            normal: self.normal,
+            t,
+            u: alpha,
+            v: beta,
+            front_face: false,
+            mat_ptr: Arc::clone(&self.mat_ptr),
+            object_id: 0,
+            material_id: self.mat_ptr.id(),
+        };
+        Some(hr.with_face_normal(r, self.normal))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let diagonal = Aabb::from_points(self.q, self.q + self.u + self.v);
+        let other = Aabb::from_points(self.q + self.u, self.q + self.v);
+        Some(diagonal.surrounding(other))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, _time: f64) -> f64 {
+        match self.hit(&Ray::new(origin, dir), 0.001, INF) {
+            Some(rec) => area_pdf(dir * rec.t, self.normal, self.area),
+            None => 0.0,
+        }
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let (a, b) = sampler.get_2d();
+        let p = self.q + self.u * a + self.v * b;
+        (p - origin).unit_vector()
+    }
+}
+
+/// Converts the density of uniformly picking a point on a flat surface of `area` into a
+/// density per unit solid angle, seen from `to_point` away.
+pub(crate) fn area_pdf(to_point: Vec3, normal: Vec3, area: f64) -> f64 {
+    let distance_squared = dot(to_point, to_point);
+    let cosine = dot(to_point, normal).abs() / distance_squared.sqrt();
+    if cosine < 1e-8 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+/// An axis-aligned box between two opposite corners, made of six outward-facing quads.
+///
+/// (Named so it doesn't shadow `std::boxed::Box`.)
+pub struct Cuboid {
+    sides: HitList,
+    bbox: Aabb,
+}
+
+impl Cuboid {
+    pub fn new(a: Point3, b: Point3, mat_ptr: Arc<dyn Material>) -> Self {
+        let bbox = Aabb::from_points(a, b);
+        let (min, max) = (bbox.min, bbox.max);
+        let d = max - min;
+        let dx = Vec3::new(d.x(), 0.0, 0.0);
+        let dy = Vec3::new(0.0, d.y(), 0.0);
+        let dz = Vec3::new(0.0, 0.0, d.z());
+
+        let mut sides = HitList::new();
+        let mut side = |q: Point3, u: Vec3, v: Vec3| {
+            sides.add(Box::new(Quad::new(q, u, v, Arc::clone(&mat_ptr))));
+        };
+        let front = Point3::new(min.x(), min.y(), max.z());
+        side(front, dx, dy); // front (+z)
+        side(front + dx, -dz, dy); // right (+x)
+        side(Point3::new(max.x(), min.y(), min.z()), -dx, dy); // back (-z)
+        side(min, dz, dy); // left (-x)
+        side(front + dy, dx, -dz); // top (+y)
+        side(min, dx, dz); // bottom (-y)
+
+        Cuboid { sides, bbox }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+
+    /// Picks a side, then a point on it.
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        self.sides.pdf_value(origin, dir, time)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        self.sides.random_direction(origin, time, sampler)
+    }
+}
+
+/// An infinite plane through `point`, facing `normal`.
+///
+/// Its UVs are distances (in scene units) along two directions in the plane, so they
+/// aren't limited to `[0, 1]`; use a repeating texture on it.
+pub struct Plane {
+    pub point: Point3,
+    pub mat_ptr: Arc<dyn Material>,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, mat_ptr: Arc<dyn Material>) -> Self {
+        let normal = normal.unit_vector();
+        // any axis that isn't (nearly) parallel to the normal gives a tangent
+        let axis = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let bitangent = normal.cross(axis).unit_vector();
+        let tangent = bitangent.cross(normal);
+        Plane {
+            point,
+            mat_ptr,
+            normal,
+            tangent,
+            bitangent,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3 {
+        self.normal
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denom = dot(self.normal, r.dir);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = dot(self.normal, self.point - r.orig) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let p = r.at(t);
+        let hr = HitRecord {
+            p,
+            normal: self.normal,
+            t,
+            u: dot(p - self.point, self.tangent),
+            v: dot(p - self.point, self.bitangent),
+            front_face: false,
+            mat_ptr: Arc::clone(&self.mat_ptr),
+            object_id: 0,
+            material_id: self.mat_ptr.id(),
+        };
+        Some(hr.with_face_normal(r, self.normal))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sampler::IndependentSampler;
+    use crate::vec3::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn test_quad_hit_and_uv() {
+        let quad = Quad::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.5, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = quad.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 5.0);
+        assert_eq!((rec.u, rec.v), (0.25, 0.75));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let miss = Ray::new(Point3::new(2.5, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(quad.hit(&miss, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_quad_sampling_pdf() {
+        // a small 0.1 x 0.1 light 10 units away subtends about 1e-4 steradians
+        let quad = Quad::new(
+            Point3::new(-0.05, -0.05, 0.0),
+            Vec3::new(0.1, 0.0, 0.0),
+            Vec3::new(0.0, 0.1, 0.0),
+            material(),
+        );
+        let origin = Point3::new(0.0, 0.0, 10.0);
+        let dir = quad.random_direction(origin, 0.0, &mut IndependentSampler);
+        // a unit vector, so shadow rays' epsilon is a distance, however far the light is
+        assert!((dir.length() - 1.0).abs() < 1e-12);
+        assert!(quad.hit(&Ray::new(origin, dir), 0.001, INF).is_some());
+        let pdf = quad.pdf_value(origin, dir, 0.0);
+        assert!((pdf - 1e4).abs() < 10.0, "{pdf}");
+        // scaling the direction doesn't change the density
+        assert!((quad.pdf_value(origin, dir * 3.0, 0.0) - pdf).abs() < 1e-6);
+        assert_eq!(quad.pdf_value(origin, Vec3::new(0.0, 1.0, 0.0), 0.0), 0.0);
+    }
+
+    #[test]
+    fn test_cuboid_faces_outward() {
+        let cuboid = Cuboid::new(
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(-1.0, -1.0, -1.0),
+            material(),
+        );
+        let axes = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for axis in axes.into_iter().flat_map(|a| [a, -a]) {
+            let r = Ray::new(axis * 5.0, -axis);
+            let rec = cuboid.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert_eq!(rec.t, 4.0);
+            assert!(rec.front_face, "side facing {axis:?}");
+            assert_eq!(rec.normal, axis);
+        }
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = Plane::new(
+            Point3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            material(),
+        );
+        assert!(plane.bounding_box().is_none());
+        let r = Ray::new(Point3::new(100.0, 1.0, -50.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = plane.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        let parallel = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(plane.hit(&parallel, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -0,0 +1,426 @@
+//! Where the random numbers for each sample come from.
+//!
+//! A `Sampler` hands out the numbers for one sample of one pixel, a dimension at a time:
+//! the camera takes the first few (position in the pixel, on the lens, in time), and each
+//! bounce takes more for picking a light and a scattering direction. Samplers other than
+//! `IndependentSampler` spread the numbers for the same dimension evenly over a pixel's
+//! samples, which makes images converge with far fewer samples than purely random ones.
+
+use serde::Deserialize;
+
+use crate::util::{random_2d, random_double, seed_rng, stream_seed};
+
+pub trait Sampler {
+    /// Starts sample number `index` (counting from 0) of a pixel, at its first
+    /// dimension. `pixel_seed` is a seed unique to the pixel, so neighbouring pixels
+    /// don't repeat the same pattern.
+    fn start_pixel_sample(&mut self, pixel_seed: u64, index: u32);
+
+    /// A number in `[0, 1)` from the next dimension.
+    fn get_1d(&mut self) -> f64;
+
+    /// A point in `[0, 1)²` from the next two dimensions.
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// Which `Sampler` to render with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    /// Uncorrelated random numbers.
+    Independent,
+    /// Jittered strata: each sample gets its own cell of every dimension.
+    Stratified,
+    /// The Halton sequence, randomly shifted per pixel.
+    Halton,
+    /// The Sobol sequence, Owen-scrambled per pixel.
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    /// A sampler for pixels of `samples_per_pixel` samples.
+    pub fn build(self, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::default()),
+            SamplerKind::Sobol => Box::new(SobolSampler::default()),
+        }
+    }
+
+    /// A sampler for samples `first..` of the pixel seeded with `pixel_seed`, with the
+    /// thread's random number stream restarted for them. Every pass over the pixel that
+    /// starts at the same sample gets the same numbers, whichever thread runs it.
+    pub fn start_pixel(
+        self,
+        samples_per_pixel: u32,
+        pixel_seed: u64,
+        first: u32,
+    ) -> Box<dyn Sampler> {
+        seed_rng(stream_seed(pixel_seed, first as u64));
+        self.build(samples_per_pixel)
+    }
+}
+
+/// Plain random numbers from the thread's random number stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, _pixel_seed: u64, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_double()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        random_2d()
+    }
+}
+
+// What every sampler but the independent one keeps track of: which sample of which pixel
+// it's on, and the next dimension to hand out.
+#[derive(Debug, Clone, Copy, Default)]
+struct SampleIndex {
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleIndex {
+    fn start(&mut self, pixel_seed: u64, index: u32) {
+        *self = SampleIndex {
+            pixel_seed,
+            index,
+            dimension: 0,
+        };
+    }
+
+    // Claims the next `n` dimensions, returning the first, with a hash of it and the
+    // pixel to scramble them by.
+    fn take(&mut self, n: u32) -> (u32, u32) {
+        let dimension = self.dimension;
+        self.dimension += n;
+        (
+            dimension,
+            stream_seed(self.pixel_seed, dimension as u64) as u32,
+        )
+    }
+}
+
+/// Jittered stratified samples for any number of samples per pixel.
+///
+/// In 1D, the `n` samples each fall in a different one of `n` equal intervals. In 2D,
+/// they're correlated multi-jittered (Kensler 2013): one per cell of a roughly square
+/// grid, and also one per row and column of a fine `n`-by-`n` grid. Which sample gets
+/// which cell is shuffled per pixel and dimension.
+#[derive(Debug, Clone, Copy)]
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    state: SampleIndex,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32) -> Self {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            state: SampleIndex::default(),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel_seed: u64, index: u32) {
+        self.state.start(pixel_seed, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let n = self.samples_per_pixel;
+        let (_, seed) = self.state.take(1);
+        // past the sample count (e.g. extra adaptive samples), strata can't help
+        if self.state.index >= n {
+            return random_double();
+        }
+        let stratum = permute(self.state.index, n, seed);
+        (stratum as f64 + random_double()) / n as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let n = self.samples_per_pixel;
+        let (_, seed) = self.state.take(2);
+        if self.state.index >= n {
+            return random_2d();
+        }
+        let columns = ((n as f64).sqrt() as u32).max(1);
+        let rows = n.div_ceil(columns);
+        let s = permute(self.state.index, n, seed.wrapping_mul(0x51633e2d));
+        let sx = permute(s % columns, columns, seed.wrapping_mul(0x68bc21eb));
+        let sy = permute(s / columns, rows, seed.wrapping_mul(0x02e5be93));
+        let (jx, jy) = random_2d();
+        (
+            (sx as f64 + (sy as f64 + jx) / rows as f64) / columns as f64,
+            (s as f64 + jy) / n as f64,
+        )
+    }
+}
+
+/// A pseudo-random permutation of `0..len`, chosen by `seed` (Kensler's hash-based
+/// permutation, which needs no table).
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let p = seed;
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            return (i.wrapping_add(p)) % len;
+        }
+    }
+}
+
+// Bases for the Halton sequence's dimensions; later dimensions fall back to random
+// numbers, as by then the path has bounced enough that it hardly matters.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// The Halton sequence: dimension `d` is the radical inverse of the sample index in the
+/// `d`th prime. Each pixel shifts every dimension by its own random offset (wrapping
+/// around), so pixels don't all share the same points.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HaltonSampler {
+    state: SampleIndex,
+}
+
+impl HaltonSampler {
+    fn sample(&mut self) -> f64 {
+        let (dimension, seed) = self.state.take(1);
+        let base = match PRIMES.get(dimension as usize) {
+            Some(&base) => base,
+            None => return random_double(),
+        };
+        let shift = seed as f64 / 2f64.powi(32);
+        (radical_inverse(base, self.state.index) + shift).fract()
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel_seed: u64, index: u32) {
+        self.state.start(pixel_seed, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.sample()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.sample(), self.sample())
+    }
+}
+
+/// `i` written in `base`, mirrored about the decimal point: 1, 2, 3, 4 in base 2 become
+/// 0.1, 0.01, 0.11, 0.001 (binary).
+fn radical_inverse(base: u32, mut i: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let (mut reversed, mut scale) = (0.0, inv_base);
+    while i > 0 {
+        reversed += (i % base) as f64 * scale;
+        i /= base;
+        scale *= inv_base;
+    }
+    reversed
+}
+
+/// The first two dimensions of the Sobol sequence, Owen-scrambled, for every pair of
+/// dimensions (Burley 2020, "Practical Hash-based Owen Scrambling").
+///
+/// Each pair shuffles the order of the samples differently, so pairs aren't correlated
+/// with each other, and scrambling keeps the points well spread while giving every pixel
+/// and pair a different set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SobolSampler {
+    state: SampleIndex,
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel_seed: u64, index: u32) {
+        self.state.start(pixel_seed, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.get_2d().0
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (_, seed) = self.state.take(2);
+        let index = nested_uniform_scramble(self.state.index, seed);
+        let x = nested_uniform_scramble(sobol_0(index), hash(seed ^ 0x9e37_79b9));
+        let y = nested_uniform_scramble(sobol_1(index), hash(seed ^ 0x7f4a_7c15));
+        (to_unit(x), to_unit(y))
+    }
+}
+
+// The first dimension of the Sobol sequence is the base-2 radical inverse.
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+fn sobol_1(mut index: u32) -> u32 {
+    // direction numbers for the primitive polynomial x + 1
+    let (mut v, mut result) = (1u32 << 31, 0);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+// Owen scrambling: flips each bit depending on the bits above it. (Laine and Karras's
+// hash does it for the bits below, hence the reversals.)
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn hash(x: u32) -> u32 {
+    stream_seed(x as u64, 0) as u32
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 2f64.powi(32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random_double;
+    use crate::vec3::{Point3, Vec3};
+    use crate::Camera;
+
+    // how many of `n` 2D samples land in each of `n` cells of a grid, for grids
+    // of 1 x n, n x 1, and as near to square as possible
+    fn max_cell_count(points: &[(f64, f64)]) -> usize {
+        let n = points.len();
+        let side = (n as f64).sqrt() as usize;
+        let grids = [(1, n), (n, 1), (side, n / side)];
+        grids
+            .into_iter()
+            .map(|(columns, rows)| {
+                let mut cells = vec![0; columns * rows];
+                for &(x, y) in points {
+                    let (i, j) = ((x * columns as f64) as usize, (y * rows as f64) as usize);
+                    cells[j * columns + i] += 1;
+                }
+                *cells.iter().max().unwrap()
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_samples_are_stratified() {
+        seed_rng(5);
+        let n = 64;
+        for kind in [
+            SamplerKind::Stratified,
+            SamplerKind::Halton,
+            SamplerKind::Sobol,
+        ] {
+            let mut sampler = kind.build(n);
+            let mut points = vec![Vec::new(); 3];
+            for index in 0..n {
+                sampler.start_pixel_sample(1234, index);
+                for dimension in &mut points {
+                    dimension.push(sampler.get_2d());
+                }
+            }
+            for dimension in &points {
+                assert!(dimension
+                    .iter()
+                    .all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+                // Halton's larger bases don't divide 64 cells evenly, but it's still
+                // better spread than random points, which would put 4 or 5 in one cell.
+                let count = max_cell_count(dimension);
+                let allowed = if kind == SamplerKind::Halton { 3 } else { 1 };
+                assert!(count <= allowed, "{kind:?}: {count} samples in one cell");
+            }
+            // each pixel gets different points
+            sampler.start_pixel_sample(1234, 0);
+            let first = sampler.get_2d();
+            sampler.start_pixel_sample(5678, 0);
+            assert_ne!(sampler.get_2d(), first, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn test_permute() {
+        for len in [1, 5, 64, 100] {
+            let mut seen: Vec<u32> = (0..len).map(|i| permute(i, len, 42)).collect();
+            seen.sort();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_passes_over_a_pixel_agree() {
+        // a lens wide enough that its sample matters too
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.5,
+            1.0,
+        );
+        let pixel_seed = stream_seed(3, 17);
+        let first_ray = || {
+            let mut sampler = SamplerKind::Independent.start_pixel(4, pixel_seed, 0);
+            sampler.start_pixel_sample(pixel_seed, 0);
+            let (s, t) = sampler.get_2d();
+            camera.get_ray(s, t, sampler.as_mut())
+        };
+        let beauty = first_ray();
+        // whatever the thread drew since, the AOV pass starts the pixel over
+        random_double();
+        let aov = first_ray();
+        assert_eq!(aov.orig, beauty.orig);
+        assert_eq!(aov.dir, beauty.dir);
+
+        let mut later = SamplerKind::Independent.start_pixel(4, pixel_seed, 2);
+        later.start_pixel_sample(pixel_seed, 2);
+        let (s, t) = later.get_2d();
+        assert_ne!(camera.get_ray(s, t, later.as_mut()).dir, beauty.dir);
+    }
+}
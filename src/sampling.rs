@@ -0,0 +1,293 @@
+//! Warping uniform random numbers into directions and points with known densities.
+//!
+//! The samplers take their random numbers as an explicit `(u1, u2)` pair in `[0, 1)²`
+//! rather than drawing them, so where the numbers come from can be swapped out (e.g.
+//! for stratified ones). Directions are in a local frame with `z` as the "up" axis; use
+//! an `Onb` to turn them into world directions.
+
+use std::f64::consts::PI;
+
+use crate::vec3::{dot, Vec3};
+
+/// An orthonormal basis `(u, v, w)`, used as a local frame around `w`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    /// A basis whose `w` axis points along `n`, which needn't be normalized.
+    pub fn from_w(n: Vec3) -> Onb {
+        let w = n.unit_vector();
+        // any axis that isn't (nearly) parallel to `w`
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).unit_vector();
+        let u = v.cross(w);
+        Onb { u, v, w }
+    }
+
+    /// Turns local coordinates into a world vector.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
+    }
+
+    /// Turns a world vector into local coordinates.
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3::new(dot(a, self.u), dot(a, self.v), dot(a, self.w))
+    }
+}
+
+/// Maps the unit square onto the unit disk, keeping nearby points nearby (Shirley and
+/// Chiu's concentric mapping). Uniform over the disk.
+pub fn concentric_disk((u1, u2): (f64, f64)) -> (f64, f64) {
+    let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// A direction in the `z > 0` hemisphere, with density `cosine_hemisphere_pdf`.
+pub fn cosine_hemisphere(sample: (f64, f64)) -> Vec3 {
+    // Malley's method: project a uniform disk sample up onto the hemisphere.
+    let (x, y) = concentric_disk(sample);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+    Vec3::new(x, y, z)
+}
+
+/// Density (per unit solid angle) of `cosine_hemisphere` picking a direction whose
+/// cosine with the `z` axis is `cos_theta`.
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / PI
+}
+
+/// A unit vector in any direction, with density `uniform_sphere_pdf()`.
+pub fn uniform_sphere((u1, u2): (f64, f64)) -> Vec3 {
+    let z = 1.0 - 2.0 * u1;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+/// A unit vector within the cone of directions making an angle of at most
+/// `acos(cos_theta_max)` with the `z` axis, with density `uniform_cone_pdf`.
+pub fn uniform_cone((u1, u2): (f64, f64), cos_theta_max: f64) -> Vec3 {
+    let z = 1.0 - u1 * (1.0 - cos_theta_max);
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f64) -> f64 {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+}
+
+/// Barycentric coordinates `(b1, b2)` of a point uniformly distributed over a triangle,
+/// weighting the vertices as `(1 - b1 - b2, b1, b2)`.
+pub fn uniform_triangle((u1, u2): (f64, f64)) -> (f64, f64) {
+    let s = u1.sqrt();
+    (s * (1.0 - u2), s * u2)
+}
+
+/// A piecewise-constant density over `[0, 1)`, proportional to `func`'s values over
+/// equal-width pieces, for picking pieces in proportion to their weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    // cdf[i] is the probability of picking a piece before i; cdf[n] = 1
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    /// Weights must not be negative. If they're all zero, every piece is equally likely.
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len().max(1);
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for i in 0..n {
+            let weight = func.get(i).copied().unwrap_or(0.0);
+            cdf.push(cdf[i] + weight / n as f64);
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// The weights' average: the integral of the (unnormalized) function over `[0, 1)`.
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    fn count(&self) -> usize {
+        self.cdf.len() - 1
+    }
+
+    /// Maps `u` in `[0, 1)` to a point `x` in `[0, 1)`, returning it with its density
+    /// and the index of its piece.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // the last piece whose cdf is at most u
+        let i = self.cdf.partition_point(|&c| c <= u).clamp(1, self.count()) - 1;
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = ((i as f64 + offset.clamp(0.0, 1.0)) / self.count() as f64).min(1.0 - 1e-12);
+        (x, self.pdf_at(i), i)
+    }
+
+    /// Density of `sample` returning a point in piece `i`.
+    fn pdf_at(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// Density of `sample` returning `x`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_at(i)
+    }
+}
+
+/// A piecewise-constant density over `[0, 1)²`, proportional to a grid of weights (e.g.
+/// an image's brightness). Picks a row by its total weight, then a column within it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `weights` are row by row, `width` to a row.
+    pub fn new(weights: &[f64], width: usize) -> Self {
+        let rows: Vec<Distribution1D> = weights
+            .chunks(width.max(1))
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::integral).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Maps `(u1, u2)` to a point `(x, y)`, returning it with its density.
+    pub fn sample(&self, (u1, u2): (f64, f64)) -> ((f64, f64), f64) {
+        let (y, row_pdf, row) = self.marginal.sample(u2);
+        let (x, column_pdf, _) = self.rows[row].sample(u1);
+        ((x, y), row_pdf * column_pdf)
+    }
+
+    /// Density of `sample` returning `(x, y)`.
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a deterministic grid of sample points covering the unit square
+    fn grid(n: usize) -> impl Iterator<Item = (f64, f64)> {
+        (0..n * n).map(move |i| {
+            let (x, y) = (i % n, i / n);
+            ((x as f64 + 0.5) / n as f64, (y as f64 + 0.5) / n as f64)
+        })
+    }
+
+    #[test]
+    fn test_onb() {
+        let onb = Onb::from_w(Vec3::new(1.0, 2.0, -3.0));
+        for (a, b) in [(onb.u, onb.v), (onb.v, onb.w), (onb.w, onb.u)] {
+            assert!(dot(a, b).abs() < 1e-12);
+        }
+        assert!((onb.u.cross(onb.v) - onb.w).length() < 1e-12);
+        let a = Vec3::new(0.3, -0.2, 0.9);
+        assert!((onb.to_local(onb.local(a)) - a).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_samplers_cover_their_domains() {
+        let n = 100;
+        let mut mean_cos = 0.0;
+        let mut mean_z = 0.0;
+        for sample in grid(n) {
+            let (x, y) = concentric_disk(sample);
+            assert!(x * x + y * y <= 1.0 + 1e-12);
+
+            let d = cosine_hemisphere(sample);
+            assert!((d.length() - 1.0).abs() < 1e-9 && d.z() >= 0.0);
+            mean_cos += d.z();
+
+            let d = uniform_sphere(sample);
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            mean_z += d.z();
+
+            let d = uniform_cone(sample, 0.5);
+            assert!((d.length() - 1.0).abs() < 1e-9 && d.z() >= 0.5 - 1e-12);
+
+            let (b1, b2) = uniform_triangle(sample);
+            assert!(b1 >= 0.0 && b2 >= 0.0 && b1 + b2 <= 1.0);
+        }
+        // E[cos] under the cosine distribution is 2/3; the sphere is balanced
+        assert!((mean_cos / (n * n) as f64 - 2.0 / 3.0).abs() < 1e-3);
+        assert!((mean_z / (n * n) as f64).abs() < 1e-9);
+        assert_eq!(concentric_disk((1.0, 0.5)), (1.0, 0.0));
+    }
+
+    #[test]
+    fn test_distributions() {
+        let d = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        assert_eq!(d.integral(), 2.0);
+        let (x, pdf, i) = d.sample(0.3);
+        assert_eq!(i, 1);
+        assert!((x - (0.25 + 0.25 * (0.3 - 0.125) / 0.375)).abs() < 1e-12);
+        assert_eq!(pdf, 1.5);
+        assert_eq!(d.pdf(x), 1.5);
+        assert_eq!(d.pdf(0.6), 0.0);
+        // never lands in the empty piece
+        assert!(grid(20).all(|(u, _)| !(0.5..0.75).contains(&d.sample(u).0)));
+
+        let flat = Distribution1D::new(vec![0.0, 0.0]);
+        assert_eq!(flat.sample(0.75), (0.75, 1.0, 1));
+
+        let d = Distribution2D::new(&[1.0, 0.0, 2.0, 1.0], 2);
+        let mut total = 0.0;
+        for sample in grid(20) {
+            let ((x, y), pdf) = d.sample(sample);
+            assert!((d.pdf(x, y) - pdf).abs() < 1e-12);
+            total += 1.0 / pdf;
+        }
+        // the average of 1 / pdf estimates the area where the density isn't zero
+        assert!((total / 400.0 - 0.75).abs() < 0.01, "{}", total / 400.0);
+    }
+}
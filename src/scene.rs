@@ -1,41 +1,101 @@
 use std::sync::Arc;
 
-use rt::material::{Dialectric, Lambertian, Material, Metal};
+use rt::material::{Dialectric, DiffuseLight, Isotropic, Lambertian, Material, MaterialIds, Metal};
+use rt::medium::ConstantMedium;
+use rt::quad::{Cuboid, Plane, Quad};
+use rt::scene_file::{CameraSettings, RenderSettings, SceneFile};
+use rt::transform::{Transform, Transformed};
 use rt::util::{random_bounded, random_double};
-use rt::vec3::{Color, Point3};
-use rt::{HitList, Sphere};
+use rt::vec3::{Color, Point3, Vec3, COLOR_BLACK};
+use rt::{Background, HitList, Hittable, Sphere};
+
+/// Looks up a built-in scene by name: `simple`, `random`, `light`, `cornell` or
+/// `cornell_smoke`.
+pub fn builtin(name: &str) -> Option<SceneFile> {
+    let ((world, lights), camera, background) = match name {
+        "simple" => (
+            (simple_scene(), HitList::new()),
+            CameraSettings::default(),
+            Background::default(),
+        ),
+        "random" => (
+            (random_scene(), HitList::new()),
+            CameraSettings::default(),
+            Background::default(),
+        ),
+        "light" => (
+            simple_light(),
+            CameraSettings {
+                lookfrom: Point3::new(26.0, 3.0, 6.0),
+                lookat: Point3::new(0.0, 2.0, 0.0),
+                aperture: 0.0,
+                ..CameraSettings::default()
+            },
+            Background::Solid(COLOR_BLACK),
+        ),
+        "cornell" => (
+            cornell_box(),
+            cornell_camera(),
+            Background::Solid(COLOR_BLACK),
+        ),
+        "cornell_smoke" => (
+            cornell_smoke(),
+            cornell_camera(),
+            Background::Solid(COLOR_BLACK),
+        ),
+        _ => return None,
+    };
+    Some(SceneFile {
+        render: RenderSettings::default(),
+        camera,
+        background,
+        world,
+        lights,
+    })
+}
+
+fn cornell_camera() -> CameraSettings {
+    CameraSettings {
+        lookfrom: Point3::new(278.0, 278.0, -800.0),
+        lookat: Point3::new(278.0, 278.0, 0.0),
+        vfov: 40.0,
+        aperture: 0.0,
+        ..CameraSettings::default()
+    }
+}
 
 pub fn simple_scene() -> HitList {
     // World
     let mut world = HitList::new();
+    let mut material_ids = MaterialIds::default();
     // ground
     world.add(Box::new(Sphere {
         center: Point3::new(0.0, -100.5, -1.0),
         radius: 100.0,
-        mat_ptr: Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0))),
+        mat_ptr: material_ids.tag(Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)))),
     }));
     // center
     world.add(Box::new(Sphere {
         center: Point3::new(0.0, 0.0, -1.0),
         radius: 0.5,
-        mat_ptr: Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5))),
+        mat_ptr: material_ids.tag(Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)))),
     }));
     // left (glass)
     world.add(Box::new(Sphere {
         center: Point3::new(-1.0, 0.0, -1.0),
         radius: 0.5,
-        mat_ptr: Arc::new(Dialectric::new(1.5)),
+        mat_ptr: material_ids.tag(Arc::new(Dialectric::new(1.5))),
     }));
     world.add(Box::new(Sphere {
         center: Point3::new(-1.0, 0.0, -1.0),
         radius: -0.4, // TODO: wat is a negative radius
-        mat_ptr: Arc::new(Dialectric::new(1.5)),
+        mat_ptr: material_ids.tag(Arc::new(Dialectric::new(1.5))),
     }));
     // right (metal)
     world.add(Box::new(Sphere {
         center: Point3::new(1.0, 0.0, -1.0),
         radius: 0.5,
-        mat_ptr: Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05)),
+        mat_ptr: material_ids.tag(Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.05))),
     }));
 
     // let radius1 = (PI / 4.0).cos();
@@ -56,13 +116,14 @@ pub fn simple_scene() -> HitList {
 
 pub fn random_scene() -> HitList {
     let mut world = HitList::new();
+    let mut material_ids = MaterialIds::default();
 
-    let ground_material: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    world.add(Box::new(Sphere {
-        center: Point3::new(0.0, -1000.0, 0.0),
-        radius: 1000.0,
-        mat_ptr: Arc::clone(&ground_material),
-    }));
+    let ground_material = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+    world.add(Box::new(Plane::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        ground_material,
+    )));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -79,34 +140,174 @@ pub fn random_scene() -> HitList {
                 if choose_mat < 0.8 {
                     // diffuse
                     let albedo = Color::new_random() * Color::new_random();
-                    sphere_material = Arc::new(Lambertian::new(albedo));
+                    sphere_material = material_ids.tag(Arc::new(Lambertian::new(albedo)));
                     world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Color::new_random_bounded(0.5, 1.0);
                     let fuzz = random_bounded(0.0, 0.5);
-                    sphere_material = Arc::new(Metal::new(albedo, fuzz));
+                    sphere_material = material_ids.tag(Arc::new(Metal::new(albedo, fuzz)));
                     world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
                 } else {
                     // glass
-                    sphere_material = Arc::new(Dialectric::new(1.5));
+                    sphere_material = material_ids.tag(Arc::new(Dialectric::new(1.5)));
                     world.add(Box::new(Sphere::new(center, 0.2, sphere_material)));
                 }
             }
         }
     }
 
-    let material1 = Arc::new(Dialectric::new(1.5));
+    let material1 = material_ids.tag(Arc::new(Dialectric::new(1.5)));
     let center1 = Point3::new(0.0, 1.0, 0.0);
     world.add(Box::new(Sphere::new(center1, 1.0, material1)));
 
-    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    let material2 = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1))));
     let center2 = Point3::new(-4.0, 1.0, 0.0);
     world.add(Box::new(Sphere::new(center2, 1.0, material2)));
 
-    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    let material3 = material_ids.tag(Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0)));
     let center3 = Point3::new(4.0, 1.0, 0.0);
     world.add(Box::new(Sphere::new(center3, 1.0, material3)));
 
     world
 }
+
+/// A dim sphere on the ground lit only by a glowing sphere above it; render with a black
+/// background. Returns the world and its lights.
+pub fn simple_light() -> (HitList, HitList) {
+    let mut world = HitList::new();
+    let mut material_ids = MaterialIds::default();
+
+    world.add(Box::new(Plane::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        material_ids.tag(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+    )));
+    world.add(Box::new(Sphere::new(
+        Point3::new(0.0, 2.0, 0.0),
+        2.0,
+        material_ids.tag(Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.8)))),
+    )));
+
+    let light = Arc::new(Sphere::new(
+        Point3::new(0.0, 7.0, 0.0),
+        2.0,
+        material_ids.tag(Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)))),
+    ));
+    let mut lights = HitList::new();
+    lights.add(Box::new(Arc::clone(&light)));
+    world.add(Box::new(light));
+
+    (world, lights)
+}
+
+/// The Cornell box: a 555-unit room with a green left wall, a red right wall, a light in
+/// the ceiling and two rotated white boxes; render with a black background. Returns the
+/// world and its lights.
+pub fn cornell_box() -> (HitList, HitList) {
+    let mut material_ids = MaterialIds::default();
+    let (mut world, lights) = cornell_room(&mut material_ids);
+    let white = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))));
+    let (tall_box, short_box) = cornell_boxes(white);
+    world.add(tall_box);
+    world.add(short_box);
+    (world, lights)
+}
+
+/// The Cornell box with its two boxes made of black and white smoke.
+pub fn cornell_smoke() -> (HitList, HitList) {
+    let mut material_ids = MaterialIds::default();
+    let (mut world, lights) = cornell_room(&mut material_ids);
+    let (tall_box, short_box) = cornell_boxes(Arc::new(Lambertian::new(COLOR_BLACK)));
+    world.add(Box::new(ConstantMedium::new(
+        tall_box,
+        0.01,
+        material_ids.tag(Arc::new(Isotropic::new(COLOR_BLACK))),
+    )));
+    world.add(Box::new(ConstantMedium::new(
+        short_box,
+        0.01,
+        material_ids.tag(Arc::new(Isotropic::new(Color::new(1.0, 1.0, 1.0)))),
+    )));
+    (world, lights)
+}
+
+/// The walls and light of the Cornell box, and the light on its own.
+fn cornell_room(material_ids: &mut MaterialIds) -> (HitList, HitList) {
+    let mut world = HitList::new();
+
+    let red = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05))));
+    let white = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))));
+    let green = material_ids.tag(Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15))));
+    let light_material =
+        material_ids.tag(Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0))));
+
+    let quad = |q: [f64; 3], u: [f64; 3], v: [f64; 3], mat: Arc<dyn Material>| {
+        Box::new(Quad::new(q.into(), u.into(), v.into(), mat))
+    };
+    world.add(quad(
+        [555.0, 0.0, 0.0],
+        [0.0, 555.0, 0.0],
+        [0.0, 0.0, 555.0],
+        green,
+    ));
+    world.add(quad(
+        [0.0, 0.0, 0.0],
+        [0.0, 555.0, 0.0],
+        [0.0, 0.0, 555.0],
+        red,
+    ));
+    let light = Arc::new(Quad::new(
+        Point3::new(343.0, 554.0, 332.0),
+        Vec3::new(-130.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -105.0),
+        light_material,
+    ));
+    let mut lights = HitList::new();
+    lights.add(Box::new(Arc::clone(&light)));
+    world.add(Box::new(light));
+    world.add(quad(
+        [0.0, 0.0, 0.0],
+        [555.0, 0.0, 0.0],
+        [0.0, 0.0, 555.0],
+        Arc::clone(&white),
+    ));
+    world.add(quad(
+        [555.0, 555.0, 555.0],
+        [-555.0, 0.0, 0.0],
+        [0.0, 0.0, -555.0],
+        Arc::clone(&white),
+    ));
+    world.add(quad(
+        [0.0, 0.0, 555.0],
+        [555.0, 0.0, 0.0],
+        [0.0, 555.0, 0.0],
+        white,
+    ));
+
+    (world, lights)
+}
+
+/// The tall and short boxes inside the Cornell box.
+fn cornell_boxes(mat: Arc<dyn Material>) -> (Box<dyn Hittable>, Box<dyn Hittable>) {
+    let tall_box = Cuboid::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 330.0, 165.0),
+        Arc::clone(&mat),
+    );
+    let short_box = Cuboid::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(165.0, 165.0, 165.0),
+        mat,
+    );
+    (
+        Box::new(Transformed::new(
+            Arc::new(tall_box),
+            Transform::translate(Vec3::new(265.0, 0.0, 295.0)) * Transform::rotate_y(15.0),
+        )),
+        Box::new(Transformed::new(
+            Arc::new(short_box),
+            Transform::translate(Vec3::new(130.0, 0.0, 65.0)) * Transform::rotate_y(-18.0),
+        )),
+    )
+}
@@ -0,0 +1,1324 @@
+//! Text scene descriptions, so scenes can change without recompiling.
+//!
+//! A scene is a TOML file with optional `[render]` and `[camera]` tables, a
+//! `[materials.<name>]` table per material and an `[[objects]]` entry per object:
+//!
+//! ```toml
+//! [render]
+//! width = 400
+//! samples_per_pixel = 100
+//!
+//! [camera]
+//! lookfrom = [13, 2, 3]
+//! vfov = 20
+//!
+//! [materials.ground]
+//! type = "lambertian"
+//! albedo = [0.5, 0.5, 0.5]
+//!
+//! [[objects]]
+//! type = "sphere"
+//! center = [0, -1000, 0]
+//! radius = 1000
+//! material = "ground"
+//! ```
+//!
+//! Objects are `sphere` (`center`, `radius`), `triangle` (`vertices`), `quad` (a
+//! parallelogram with corner `q` and sides `u` and `v`), `box` (axis-aligned, between
+//! corners `min` and `max`), `plane` (infinite, through `point` facing `normal`) and
+//! `mesh` (a `.obj` file at `path`). Boxes and meshes can also be placed by a
+//! `transform` table with any of `scale` (a number or per-axis factors), `rotate`
+//! (degrees about x, then y, then z) and `translate`, applied in that order. Every
+//! transformed mesh from the same file shares one copy of its triangles.
+//!
+//! A `medium` object fills a `boundary` (a `sphere` or `box` table, without a material)
+//! with fog or smoke of a given `density`, scattering as its `material`, which is
+//! normally `type = "isotropic"` with an `albedo`.
+//!
+//! Every object but planes and media is sampled directly as a light if it has a
+//! `diffuse_light` material, which cuts the noise from small, bright lights a lot. A
+//! glowing mesh is sampled a triangle at a time (only the faces using its `material`,
+//! not those with their own `.mtl` material), so keep emissive meshes small.
+//!
+//! For motion blur, open the camera's shutter (`shutter_open`/`shutter_close` in
+//! `[camera]`, e.g. 0 and 1) and use `moving_sphere` objects (`center0` at `time0` to
+//! `center1` at `time1`), or give a box or mesh an `end_transform` to move it from
+//! `transform` at time 0 to `end_transform` at time 1.
+//!
+//! Wherever a material takes a color (`albedo`, `emit`), it can instead name a texture
+//! from a `[textures.<name>]` table: `type = "solid"` with a `color`, `type = "checker"`
+//! with `even` and `odd` colors (or texture names) and a cell `scale`,
+//! `type = "image"` with a PNG/PPM `path` and a `wrap` mode (`repeat`, `clamp` or
+//! `mirror`), or one of the procedural `noise`, `marble` and `wood` textures. Those take
+//! a `seed`, a `scale` (frequency) and a number of `octaves`, plus `base`/`vein` colors
+//! for marble and `light`/`dark` colors for wood.
+//!
+//! A `[background]` table sets what rays that escape the scene see: `type = "solid"`
+//! with a `color`, `type = "gradient"` with `horizon` and `zenith` colors, or
+//! `type = "environment"` with the `path` of an equirectangular image (ideally `.hdr` or
+//! `.pfm`), turned `rotation` degrees about the vertical axis and scaled by `intensity`.
+//! It can also be `type = "sky"`, a physically based daylight sky with the sun towards
+//! `sun_direction`, a `turbidity` (haziness, from about 2 to 10; default 3) and a
+//! `ground_albedo` below the horizon (default 0.3). It defaults to the usual
+//! white-to-blue sky. Environment maps and the sky's sun light the scene, and are
+//! sampled like lights.
+//!
+//! For 8- and 16-bit images, `[render]` can also brighten or darken the image by an
+//! `exposure` (in stops), compress highlights with a `tone_map` (`clamp`, the default,
+//! `reinhard`, `extended_reinhard` up to a `white_point`, `aces` or `agx`) and `dither`
+//! (`none`, `ordered` or `blue_noise`) to hide banding in smooth gradients. Its `aovs`
+//! list asks for auxiliary images of what the camera sees first (`normal`, `albedo`,
+//! `depth`, `position`, `object_id` and `material_id`; see `aov`).
+//!
+//! Relative paths (e.g. of `mesh` objects) are resolved against the scene file's directory.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::aov::Aov;
+use crate::bvh::BvhNode;
+use crate::environment::EnvironmentMap;
+use crate::material::{
+    Dialectric, DiffuseLight, Isotropic, Lambertian, Material, MaterialIds, Metal,
+};
+use crate::medium::ConstantMedium;
+use crate::obj::{load_obj, ObjError, ObjModel};
+use crate::quad::{Cuboid, Plane, Quad};
+use crate::sampler::SamplerKind;
+use crate::sky::PhysicalSky;
+use crate::texture::{
+    CheckerTexture, ImageTexture, MarbleTexture, NoiseTexture, SolidColor, Texture, WoodTexture,
+    WrapMode,
+};
+use crate::tonemap::{Dither, ToneMap, ToneMapping};
+use crate::transform::{Moving, Transform, Transformed};
+use crate::triangle::Triangle;
+use crate::vec3::{Color, Point3, Vec3};
+use crate::{Background, Camera, HitList, Hittable, MovingSphere, Sphere};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    /// The file parsed, but `entry` (e.g. `objects[3]`) doesn't make sense.
+    Invalid {
+        path: PathBuf,
+        entry: String,
+        message: String,
+    },
+    Obj {
+        entry: String,
+        source: ObjError,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Invalid {
+                path,
+                entry,
+                message,
+            } => write!(f, "{}: {}: {}", path.display(), entry, message),
+            SceneError::Obj { entry, source } => write!(f, "{}: {}", entry, source),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Parse { source, .. } => Some(source),
+            SceneError::Invalid { .. } => None,
+            SceneError::Obj { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Output size and quality.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: i32,
+    /// Bounces after which paths may be ended early by Russian roulette.
+    pub roulette_depth: i32,
+    /// How to pick the random numbers for each pixel's samples.
+    pub sampler: SamplerKind,
+    /// If set, pixels stop taking samples once their estimated error (see
+    /// `adaptive::PixelStats::error`) is below this, and noisier pixels get the
+    /// samples they didn't use; `samples_per_pixel` becomes the average.
+    pub adaptive_threshold: Option<f64>,
+    /// Base seed for all random numbers, so renders are reproducible.
+    pub seed: u64,
+    /// How radiance becomes display colors, for image formats that need them.
+    pub tone_mapping: ToneMapping,
+    /// Auxiliary images to write along with the main one.
+    pub aovs: Vec<Aov>,
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            image_width: 1200,
+            image_height: 800,
+            samples_per_pixel: 500,
+            max_depth: 50,
+            roulette_depth: 3,
+            sampler: SamplerKind::default(),
+            adaptive_threshold: None,
+            seed: 0,
+            tone_mapping: ToneMapping::default(),
+            aovs: Vec::new(),
+        }
+    }
+}
+
+/// Everything needed to build a `Camera` except the aspect ratio, which comes from the
+/// image size.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    /// vertical field-of-view (degrees)
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    /// Moving objects are blurred over the time the shutter is open.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl CameraSettings {
+    pub fn build(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            self.aperture,
+            self.focus_dist,
+        )
+        .with_shutter(self.shutter_open, self.shutter_close)
+    }
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            lookfrom: Point3::new(13.0, 2.0, 3.0),
+            lookat: Point3::new(0.0, 0.0, 0.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 20.0,
+            aperture: 0.1,
+            focus_dist: 10.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+/// A loaded scene file.
+pub struct SceneFile {
+    pub render: RenderSettings,
+    pub camera: CameraSettings,
+    pub background: Background,
+    pub world: HitList,
+    /// Emitting objects that can be sampled directly; they're in `world` as well.
+    pub lights: HitList,
+}
+
+impl SceneFile {
+    pub fn camera(&self) -> Camera {
+        self.camera.build(self.render.aspect_ratio())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScene {
+    #[serde(default)]
+    render: RawRender,
+    #[serde(default)]
+    camera: CameraSettings,
+    background: Option<RawBackground>,
+    #[serde(default)]
+    textures: BTreeMap<String, RawTexture>,
+    // spanned to recover the order they're declared in, for material IDs
+    #[serde(default)]
+    materials: BTreeMap<String, Spanned<RawMaterial>>,
+    #[serde(default)]
+    objects: Vec<RawObject>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RawRender {
+    width: Option<u32>,
+    height: Option<u32>,
+    aspect_ratio: Option<f64>,
+    samples_per_pixel: Option<u32>,
+    max_depth: Option<i32>,
+    roulette_depth: Option<i32>,
+    sampler: Option<SamplerKind>,
+    adaptive_threshold: Option<f64>,
+    seed: Option<u64>,
+    exposure: Option<f64>,
+    tone_map: Option<ToneMap>,
+    white_point: Option<f64>,
+    dither: Option<Dither>,
+    aovs: Option<Vec<Aov>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawBackground {
+    Solid {
+        color: Color,
+    },
+    Gradient {
+        horizon: Color,
+        zenith: Color,
+    },
+    Environment {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    Sky {
+        sun_direction: Vec3,
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_ground_albedo")]
+        ground_albedo: Color,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_turbidity() -> f64 {
+    3.0
+}
+
+fn default_ground_albedo() -> Color {
+    Color::new(0.3, 0.3, 0.3)
+}
+
+/// A color, or the name of a texture.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawTextureRef {
+    Color(Color),
+    Named(String),
+}
+
+fn default_texture_scale() -> f64 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    7
+}
+
+fn default_marble_base() -> Color {
+    Color::new(0.9, 0.9, 0.9)
+}
+
+fn default_marble_vein() -> Color {
+    Color::new(0.2, 0.2, 0.25)
+}
+
+fn default_wood_light() -> Color {
+    Color::new(0.75, 0.55, 0.35)
+}
+
+fn default_wood_dark() -> Color {
+    Color::new(0.45, 0.28, 0.15)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawTexture {
+    Solid {
+        color: Color,
+    },
+    Checker {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        even: RawTextureRef,
+        odd: RawTextureRef,
+    },
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+    },
+    Noise {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+    },
+    Marble {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_marble_base")]
+        base: Color,
+        #[serde(default = "default_marble_vein")]
+        vein: Color,
+    },
+    Wood {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default = "default_wood_light")]
+        light: Color,
+        #[serde(default = "default_wood_dark")]
+        dark: Color,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawMaterial {
+    Lambertian {
+        albedo: RawTextureRef,
+    },
+    Metal {
+        albedo: RawTextureRef,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    #[serde(alias = "dialectric")]
+    Dielectric {
+        ior: f64,
+    },
+    DiffuseLight {
+        emit: RawTextureRef,
+    },
+    /// Scatters in every direction; for `medium` objects.
+    Isotropic {
+        albedo: RawTextureRef,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawObject {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: String,
+    },
+    /// A sphere moving from `center0` at `time0` to `center1` at `time1`.
+    MovingSphere {
+        center0: Point3,
+        center1: Point3,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [Point3; 3],
+        material: String,
+    },
+    /// A parallelogram with a corner at `q` and sides `u` and `v`.
+    Quad {
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        material: String,
+    },
+    /// An axis-aligned box between two opposite corners.
+    Box {
+        min: Point3,
+        max: Point3,
+        material: String,
+        transform: Option<RawTransform>,
+        /// If given, the box moves from `transform` at time 0 to this at time 1.
+        end_transform: Option<RawTransform>,
+    },
+    Plane {
+        point: Point3,
+        normal: Vec3,
+        material: String,
+    },
+    /// Fog or smoke of uniform `density` filling `boundary`.
+    Medium {
+        boundary: RawBoundary,
+        density: f64,
+        material: String,
+    },
+    /// A Wavefront `.obj` file. Groups without an MTL material use `material`.
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+        transform: Option<RawTransform>,
+        /// If given, the mesh moves from `transform` at time 0 to this at time 1.
+        end_transform: Option<RawTransform>,
+    },
+}
+
+/// The shape of a `medium`.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum RawBoundary {
+    Sphere {
+        center: Point3,
+        radius: f64,
+    },
+    Box {
+        min: Point3,
+        max: Point3,
+        transform: Option<RawTransform>,
+    },
+}
+
+fn default_time1() -> f64 {
+    1.0
+}
+
+/// Scale, then rotate (about x, then y, then z, in degrees), then translate.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTransform {
+    scale: Option<RawScale>,
+    rotate: Option<Vec3>,
+    translate: Option<Vec3>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawScale {
+    Uniform(f64),
+    Axes(Vec3),
+}
+
+/// Reads and builds the scene at `path`.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<SceneFile, SceneError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_scene(&source, path)
+}
+
+/// Builds a scene from the contents of a scene file; `path` is used for error messages
+/// and to resolve relative paths.
+pub fn parse_scene(source: &str, path: &Path) -> Result<SceneFile, SceneError> {
+    let raw: RawScene = toml::from_str(source).map_err(|source| SceneError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let invalid = |entry: String, message: String| SceneError::Invalid {
+        path: path.to_path_buf(),
+        entry,
+        message,
+    };
+
+    let render = build_render(&raw.render).map_err(|message| invalid("render".into(), message))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut textures = Textures {
+        raw: &raw.textures,
+        base_dir,
+        built: BTreeMap::new(),
+        in_progress: Vec::new(),
+    };
+    for name in raw.textures.keys() {
+        textures
+            .get(name, "")
+            .map_err(|(entry, message)| invalid(entry, message))?;
+    }
+
+    let mut declared: Vec<_> = raw.materials.iter().collect();
+    declared.sort_by_key(|(_, mat)| mat.span().start);
+    let mut material_ids = MaterialIds::default();
+    let mut materials: BTreeMap<&str, Arc<dyn Material>> = BTreeMap::new();
+    for (name, mat) in declared {
+        let entry = format!("materials.{name}");
+        let mat = build_material(mat.get_ref(), &mut textures, &entry)
+            .map_err(|(entry, message)| invalid(entry, message))?;
+        materials.insert(name, material_ids.tag(mat));
+    }
+
+    let mut world = HitList::new();
+    let mut lights = HitList::new();
+    let is_light = |name: &str| {
+        matches!(
+            raw.materials.get(name).map(Spanned::get_ref),
+            Some(RawMaterial::DiffuseLight { .. })
+        )
+    };
+    // each mesh instance, along with its glowing triangles if it has any
+    let mut instances: BTreeMap<(PathBuf, Option<String>), MeshInstance> = BTreeMap::new();
+    for (i, obj) in raw.objects.iter().enumerate() {
+        let entry = format!("objects[{i}]");
+        let lookup = |name: &str| {
+            materials
+                .get(name)
+                .map(Arc::clone)
+                .ok_or_else(|| invalid(entry.clone(), format!("unknown material `{name}`")))
+        };
+        match obj {
+            RawObject::Sphere {
+                center,
+                radius,
+                material,
+            } => add_object(
+                &mut world,
+                &mut lights,
+                Arc::new(Sphere::new(*center, *radius, lookup(material)?)),
+                is_light(material),
+            ),
+            RawObject::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => add_object(
+                &mut world,
+                &mut lights,
+                Arc::new(MovingSphere::new(
+                    (*center0, *time0),
+                    (*center1, *time1),
+                    *radius,
+                    lookup(material)?,
+                )),
+                is_light(material),
+            ),
+            RawObject::Triangle { vertices, material } => add_object(
+                &mut world,
+                &mut lights,
+                Arc::new(Triangle::new(
+                    vertices[0],
+                    vertices[1],
+                    vertices[2],
+                    lookup(material)?,
+                )),
+                is_light(material),
+            ),
+            RawObject::Quad { q, u, v, material } => {
+                if u.cross(*v).near_zero() {
+                    return Err(invalid(
+                        entry.clone(),
+                        "`u` and `v` must not be parallel".into(),
+                    ));
+                }
+                add_object(
+                    &mut world,
+                    &mut lights,
+                    Arc::new(Quad::new(*q, *u, *v, lookup(material)?)),
+                    is_light(material),
+                )
+            }
+            RawObject::Box {
+                min,
+                max,
+                material,
+                transform,
+                end_transform,
+            } => {
+                let cuboid = Arc::new(Cuboid::new(*min, *max, lookup(material)?));
+                let placed = place(cuboid, transform.as_ref(), end_transform.as_ref())
+                    .map_err(|message| invalid(entry.clone(), message))?;
+                add_object(
+                    &mut world,
+                    &mut lights,
+                    Arc::from(placed),
+                    is_light(material),
+                );
+            }
+            RawObject::Plane {
+                point,
+                normal,
+                material,
+            } => {
+                if normal.near_zero() {
+                    return Err(invalid(entry.clone(), "`normal` must not be zero".into()));
+                }
+                world.add(Box::new(Plane::new(*point, *normal, lookup(material)?)))
+            }
+            RawObject::Medium {
+                boundary,
+                density,
+                material,
+            } => {
+                if *density <= 0.0 {
+                    return Err(invalid(
+                        entry.clone(),
+                        format!("`density` must be positive, got {density}"),
+                    ));
+                }
+                // the boundary is only used for its shape, so its material doesn't matter
+                let phase_function = lookup(material)?;
+                let boundary: Box<dyn Hittable> = match boundary {
+                    RawBoundary::Sphere { center, radius } => {
+                        Box::new(Sphere::new(*center, *radius, Arc::clone(&phase_function)))
+                    }
+                    RawBoundary::Box {
+                        min,
+                        max,
+                        transform,
+                    } => {
+                        let cuboid = Cuboid::new(*min, *max, Arc::clone(&phase_function));
+                        place(Arc::new(cuboid), transform.as_ref(), None)
+                            .map_err(|message| invalid(entry.clone(), message))?
+                    }
+                };
+                world.add(Box::new(ConstantMedium::new(
+                    boundary,
+                    *density,
+                    phase_function,
+                )));
+            }
+            RawObject::Mesh {
+                path,
+                material,
+                transform,
+                end_transform,
+            } => {
+                let default_material = match material {
+                    Some(name) => lookup(name)?,
+                    None => material_ids.tag(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))),
+                };
+                let glows = material.as_deref().is_some_and(is_light);
+                if transform.is_none() && end_transform.is_none() {
+                    let model = load_obj(base_dir.join(path))
+                        .map_err(|source| SceneError::Obj { entry, source })?;
+                    let (triangles, glowing) =
+                        mesh_parts(&model, default_material, &mut material_ids, glows);
+                    world.add_parts(triangles);
+                    if let Some(glowing) = glowing {
+                        lights.add(Box::new(glowing));
+                    }
+                    continue;
+                }
+
+                // Transformed meshes are instances: each file (with a given material) is
+                // loaded once, and every instance shares its triangles.
+                let key = (path.clone(), material.clone());
+                let (mesh, glowing) = match instances.get(&key) {
+                    Some((mesh, glowing)) => (Arc::clone(mesh), glowing.clone()),
+                    None => {
+                        let model =
+                            load_obj(base_dir.join(path)).map_err(|source| SceneError::Obj {
+                                entry: entry.clone(),
+                                source,
+                            })?;
+                        let (triangles, glowing) =
+                            mesh_parts(&model, default_material, &mut material_ids, glows);
+                        let mesh: Arc<dyn Hittable> = Arc::new(BvhNode::new(triangles));
+                        let glowing = glowing.map(|list| Arc::new(list) as Arc<dyn Hittable>);
+                        instances.insert(key, (Arc::clone(&mesh), glowing.clone()));
+                        (mesh, glowing)
+                    }
+                };
+                let placed = place(mesh, transform.as_ref(), end_transform.as_ref())
+                    .map_err(|message| invalid(entry.clone(), message))?;
+                world.add(placed);
+                if let Some(glowing) = glowing {
+                    let placed = place(glowing, transform.as_ref(), end_transform.as_ref())
+                        .map_err(|message| invalid(entry, message))?;
+                    lights.add(placed);
+                }
+            }
+        }
+    }
+
+    let background = match raw.background {
+        None => Background::default(),
+        Some(RawBackground::Solid { color }) => Background::Solid(color),
+        Some(RawBackground::Gradient { horizon, zenith }) => {
+            Background::Gradient { horizon, zenith }
+        }
+        Some(RawBackground::Environment {
+            path,
+            rotation,
+            intensity,
+        }) => {
+            let path = base_dir.join(path);
+            let map = EnvironmentMap::load(&path, rotation, intensity).map_err(|err| {
+                invalid(
+                    "background".to_string(),
+                    format!("{}: {err}", path.display()),
+                )
+            })?;
+            Background::Environment(Arc::new(map))
+        }
+        Some(RawBackground::Sky {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            intensity,
+        }) => {
+            if sun_direction.near_zero() {
+                let message = "`sun_direction` must not be zero".to_string();
+                return Err(invalid("background".to_string(), message));
+            }
+            let sky = PhysicalSky::new(sun_direction, turbidity, ground_albedo, intensity);
+            Background::Sky(Arc::new(sky))
+        }
+    };
+
+    Ok(SceneFile {
+        render,
+        camera: raw.camera,
+        background,
+        world,
+        lights,
+    })
+}
+
+/// Adds `object` to the world, and to `lights` as well if it's a light, so it can be
+/// sampled directly.
+fn add_object(
+    world: &mut HitList,
+    lights: &mut HitList,
+    object: Arc<dyn Hittable>,
+    is_light: bool,
+) {
+    if is_light {
+        lights.add(Box::new(Arc::clone(&object)));
+    }
+    world.add(Box::new(object));
+}
+
+type MeshInstance = (Arc<dyn Hittable>, Option<Arc<dyn Hittable>>);
+
+/// A model's triangles, plus (if `glows`) a separate list of the ones using
+/// `default_material` to sample as a light.
+fn mesh_parts(
+    model: &ObjModel,
+    default_material: Arc<dyn Material>,
+    material_ids: &mut MaterialIds,
+    glows: bool,
+) -> (HitList, Option<HitList>) {
+    let mut triangles = HitList::new();
+    let mut glowing = HitList::new();
+    for mesh in model.meshes(Arc::clone(&default_material), material_ids) {
+        let mesh = Arc::new(mesh);
+        if glows && Arc::ptr_eq(&mesh.mat_ptr, &default_material) {
+            glowing.add_parts(mesh.triangles());
+        }
+        triangles.add_parts(mesh.triangles());
+    }
+    (triangles, (!glowing.objects.is_empty()).then_some(glowing))
+}
+
+fn build_render(raw: &RawRender) -> Result<RenderSettings, String> {
+    let defaults = RenderSettings::default();
+    let image_width = raw.width.unwrap_or(defaults.image_width);
+    let image_height = match (raw.height, raw.aspect_ratio) {
+        (Some(_), Some(_)) => return Err("give either `height` or `aspect_ratio`, not both".into()),
+        (Some(height), None) => height,
+        (None, Some(aspect_ratio)) if aspect_ratio > 0.0 => {
+            (image_width as f64 / aspect_ratio) as u32
+        }
+        (None, Some(aspect_ratio)) => {
+            return Err(format!(
+                "`aspect_ratio` must be positive, got {aspect_ratio}"
+            ))
+        }
+        (None, None) => (image_width as f64 / defaults.aspect_ratio()) as u32,
+    };
+    if image_width == 0 || image_height == 0 {
+        return Err(format!(
+            "image must be at least 1x1, got {image_width}x{image_height}"
+        ));
+    }
+    let samples_per_pixel = raw.samples_per_pixel.unwrap_or(defaults.samples_per_pixel);
+    if samples_per_pixel == 0 {
+        return Err("`samples_per_pixel` must be at least 1, got 0".into());
+    }
+    let max_depth = raw.max_depth.unwrap_or(defaults.max_depth);
+    if max_depth < 1 {
+        return Err(format!("`max_depth` must be at least 1, got {max_depth}"));
+    }
+    if let Some(threshold) = raw.adaptive_threshold {
+        if threshold <= 0.0 {
+            return Err(format!(
+                "`adaptive_threshold` must be positive, got {threshold}"
+            ));
+        }
+    }
+    let tone_defaults = defaults.tone_mapping;
+    let white_point = raw.white_point.unwrap_or(tone_defaults.white_point);
+    if white_point <= 0.0 {
+        return Err(format!("`white_point` must be positive, got {white_point}"));
+    }
+
+    Ok(RenderSettings {
+        image_width,
+        image_height,
+        samples_per_pixel,
+        max_depth,
+        roulette_depth: raw.roulette_depth.unwrap_or(defaults.roulette_depth),
+        sampler: raw.sampler.unwrap_or(defaults.sampler),
+        adaptive_threshold: raw.adaptive_threshold,
+        seed: raw.seed.unwrap_or(defaults.seed),
+        tone_mapping: ToneMapping {
+            exposure: raw.exposure.unwrap_or(tone_defaults.exposure),
+            operator: raw.tone_map.unwrap_or(tone_defaults.operator),
+            white_point,
+            dither: raw.dither.unwrap_or(tone_defaults.dither),
+        },
+        aovs: raw.aovs.clone().unwrap_or(defaults.aovs),
+    })
+}
+
+// Errors are `(entry, message)`, since they may come from a texture the material uses.
+fn build_material(
+    raw: &RawMaterial,
+    textures: &mut Textures,
+    entry: &str,
+) -> Result<Arc<dyn Material>, (String, String)> {
+    Ok(match raw {
+        RawMaterial::Lambertian { albedo } => {
+            Arc::new(Lambertian::with_texture(textures.resolve(albedo, entry)?))
+        }
+        RawMaterial::Metal { albedo, fuzz } => {
+            if !(0.0..=1.0).contains(fuzz) {
+                return Err((
+                    entry.to_string(),
+                    format!("`fuzz` must be between 0 and 1, got {fuzz}"),
+                ));
+            }
+            Arc::new(Metal::with_texture(textures.resolve(albedo, entry)?, *fuzz))
+        }
+        RawMaterial::Dielectric { ior } => Arc::new(Dialectric::new(*ior)),
+        RawMaterial::DiffuseLight { emit } => {
+            Arc::new(DiffuseLight::with_texture(textures.resolve(emit, entry)?))
+        }
+        RawMaterial::Isotropic { albedo } => {
+            Arc::new(Isotropic::with_texture(textures.resolve(albedo, entry)?))
+        }
+    })
+}
+
+/// Wraps `object` in whatever transform (or motion between two transforms) it was given.
+fn place(
+    object: Arc<dyn Hittable>,
+    transform: Option<&RawTransform>,
+    end_transform: Option<&RawTransform>,
+) -> Result<Box<dyn Hittable>, String> {
+    let start = transform.map(build_transform).transpose()?;
+    Ok(match (start, end_transform) {
+        (None, None) => Box::new(object),
+        (Some(start), None) => Box::new(Transformed::new(object, start)),
+        (start, Some(end)) => Box::new(Moving::new(
+            object,
+            (start.unwrap_or(Transform::IDENTITY), 0.0),
+            (build_transform(end)?, 1.0),
+        )),
+    })
+}
+
+fn build_transform(raw: &RawTransform) -> Result<Transform, String> {
+    let mut transform = Transform::IDENTITY;
+    if let Some(scale) = &raw.scale {
+        let factors = match *scale {
+            RawScale::Uniform(s) => Vec3::new(s, s, s),
+            RawScale::Axes(factors) => factors,
+        };
+        if factors.x() == 0.0 || factors.y() == 0.0 || factors.z() == 0.0 {
+            return Err("`scale` must not be zero".to_string());
+        }
+        transform = Transform::scale(factors);
+    }
+    if let Some(degrees) = raw.rotate {
+        transform = Transform::rotate_z(degrees.z())
+            * Transform::rotate_y(degrees.y())
+            * Transform::rotate_x(degrees.x())
+            * transform;
+    }
+    if let Some(offset) = raw.translate {
+        transform = Transform::translate(offset) * transform;
+    }
+    Ok(transform)
+}
+
+/// Builds named textures on first use, so they can refer to each other in any order.
+struct Textures<'a> {
+    raw: &'a BTreeMap<String, RawTexture>,
+    base_dir: &'a Path,
+    built: BTreeMap<&'a str, Arc<dyn Texture>>,
+    in_progress: Vec<&'a str>,
+}
+
+impl<'a> Textures<'a> {
+    /// Returns texture `name`. An unknown name is reported against `entry`, the entry
+    /// that referred to it.
+    fn get(&mut self, name: &str, entry: &str) -> Result<Arc<dyn Texture>, (String, String)> {
+        if let Some(texture) = self.built.get(name) {
+            return Ok(Arc::clone(texture));
+        }
+        let (name, raw) = self
+            .raw
+            .get_key_value(name)
+            .ok_or_else(|| (entry.to_string(), format!("unknown texture `{name}`")))?;
+        let entry = format!("textures.{name}");
+        if self.in_progress.contains(&name.as_str()) {
+            return Err((entry, "texture refers to itself".to_string()));
+        }
+
+        self.in_progress.push(name);
+        let texture: Arc<dyn Texture> = match raw {
+            RawTexture::Solid { color } => Arc::new(SolidColor::new(*color)),
+            RawTexture::Checker { scale, even, odd } => {
+                if *scale <= 0.0 {
+                    return Err((entry, format!("`scale` must be positive, got {scale}")));
+                }
+                Arc::new(CheckerTexture::new(
+                    *scale,
+                    self.resolve(even, &entry)?,
+                    self.resolve(odd, &entry)?,
+                ))
+            }
+            RawTexture::Image { path, wrap } => {
+                let path = self.base_dir.join(path);
+                let image = ImageTexture::load(&path, *wrap)
+                    .map_err(|err| (entry, format!("{}: {err}", path.display())))?;
+                Arc::new(image)
+            }
+            RawTexture::Noise {
+                seed,
+                scale,
+                octaves,
+            } => Arc::new(NoiseTexture::new(*seed, *scale, *octaves)),
+            RawTexture::Marble {
+                seed,
+                scale,
+                octaves,
+                base,
+                vein,
+            } => Arc::new(MarbleTexture::new(*seed, *scale, *octaves, *base, *vein)),
+            RawTexture::Wood {
+                seed,
+                scale,
+                octaves,
+                light,
+                dark,
+            } => Arc::new(WoodTexture::new(*seed, *scale, *octaves, *light, *dark)),
+        };
+        self.in_progress.pop();
+
+        self.built.insert(name, Arc::clone(&texture));
+        Ok(texture)
+    }
+
+    fn resolve(
+        &mut self,
+        raw: &RawTextureRef,
+        entry: &str,
+    ) -> Result<Arc<dyn Texture>, (String, String)> {
+        match raw {
+            RawTextureRef::Color(color) => Ok(Arc::new(SolidColor::new(*color))),
+            RawTextureRef::Named(name) => self.get(name, entry),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Hittable, Ray};
+
+    #[test]
+    fn test_parse_scene() {
+        let source = r#"
+[render]
+width = 300
+aspect_ratio = 1.5
+samples_per_pixel = 10
+exposure = -1
+tone_map = "extended_reinhard"
+dither = "blue_noise"
+aovs = ["albedo", "object_id"]
+
+[camera]
+lookfrom = [0, 0, 5]
+vfov = 40
+
+[background]
+type = "solid"
+color = [0, 0, 0]
+
+[textures.checker]
+type = "checker"
+scale = 0.5
+even = "white"
+odd = [0, 0, 0]
+
+[textures.white]
+type = "solid"
+color = [1, 1, 1]
+
+[textures.marble]
+type = "marble"
+seed = 7
+scale = 4
+
+[materials.red]
+type = "lambertian"
+albedo = [0.8, 0.1, 0.1]
+
+[materials.checkered]
+type = "metal"
+albedo = "checker"
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "red"
+
+[[objects]]
+type = "box"
+min = [-1, -1, -1]
+max = [1, 1, 1]
+material = "checkered"
+transform = { rotate = [0, 45, 0], translate = [10, 0, 0] }
+
+[[objects]]
+type = "moving_sphere"
+center0 = [-10, 0, 0]
+center1 = [-10, 4, 0]
+radius = 1
+material = "red"
+"#;
+        let scene = parse_scene(source, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.render.image_width, 300);
+        assert_eq!(scene.render.image_height, 200);
+        assert_eq!(scene.render.samples_per_pixel, 10);
+        assert_eq!(scene.render.max_depth, 50);
+        assert_eq!(scene.render.roulette_depth, 3);
+        assert_eq!(
+            scene.render.tone_mapping,
+            ToneMapping {
+                exposure: -1.0,
+                operator: ToneMap::ExtendedReinhard,
+                white_point: 4.0,
+                dither: Dither::BlueNoise,
+            }
+        );
+        assert_eq!(scene.render.aovs, vec![Aov::Albedo, Aov::ObjectId]);
+        assert_eq!(scene.camera.lookfrom, Point3::new(0.0, 0.0, 5.0));
+        assert_eq!(scene.camera.vfov, 40.0);
+        assert_eq!(
+            scene.background,
+            Background::Solid(Color::new(0.0, 0.0, 0.0))
+        );
+
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.0);
+        // materials are numbered in the order they're declared, not by name
+        assert_eq!(rec.material_id, 1);
+
+        // the rotated box has an edge towards the camera
+        let r = Ray::new(Point3::new(10.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (5.0 - 2f64.sqrt())).abs() < 1e-9, "{}", rec.t);
+        assert_eq!(rec.material_id, 2);
+
+        // the moving sphere is only at y = 4 at the end of its motion
+        let (origin, dir) = (Point3::new(-10.0, 4.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit_at = |time| {
+            scene
+                .world
+                .hit(&Ray::with_time(origin, dir, time), 0.001, 100.0)
+        };
+        assert!(hit_at(0.0).is_none());
+        assert_eq!(hit_at(1.0).unwrap().t, 4.0);
+    }
+
+    #[test]
+    fn test_parse_scene_samples_glowing_boxes_and_meshes() {
+        let source = r#"
+[materials.light]
+type = "diffuse_light"
+emit = [4, 4, 4]
+
+[materials.red]
+type = "lambertian"
+albedo = [0.8, 0.1, 0.1]
+
+[[objects]]
+type = "box"
+min = [-1, -1, -1]
+max = [1, 1, 1]
+material = "light"
+transform = { translate = [0, 10, 0] }
+
+[[objects]]
+type = "moving_sphere"
+center0 = [10, 0, 0]
+center1 = [10, 4, 0]
+radius = 1
+material = "light"
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "red"
+"#;
+        let scene = parse_scene(source, Path::new("test.toml")).unwrap();
+        assert_eq!(scene.lights.objects.len(), 2);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        assert!(
+            scene
+                .lights
+                .pdf_value(origin, Vec3::new(0.0, 1.0, 0.0), 0.0)
+                > 0.0
+        );
+        assert!(
+            scene
+                .lights
+                .pdf_value(origin, Vec3::new(10.0, 4.0, 0.0), 1.0)
+                > 0.0
+        );
+        assert_eq!(
+            scene
+                .lights
+                .pdf_value(origin, Vec3::new(10.0, 4.0, 0.0), 0.0),
+            0.0
+        );
+
+        let model =
+            crate::obj::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
+        let mut material_ids = MaterialIds::default();
+        let (triangles, glowing) = mesh_parts(&model, Arc::clone(&light), &mut material_ids, true);
+        assert_eq!(triangles.objects.len(), 2);
+        assert_eq!(glowing.unwrap().objects.len(), 2);
+        let (_, glowing) = mesh_parts(&model, light, &mut material_ids, false);
+        assert!(glowing.is_none());
+    }
+
+    #[test]
+    fn test_parse_scene_errors_name_the_entry() {
+        let source = r#"
+[materials.red]
+type = "lambertian"
+albedo = [0.8, 0.1, 0.1]
+
+[[objects]]
+type = "sphere"
+center = [0, 0, 0]
+radius = 1
+material = "red"
+
+[[objects]]
+type = "sphere"
+center = [0, 1, 0]
+radius = 1
+material = "blue"
+"#;
+        let err = parse_scene(source, Path::new("test.toml")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.toml: objects[1]: unknown material `blue`"
+        );
+
+        let err = parse_scene("[[objects]]\ntype = \"cube\"\n", Path::new("test.toml"))
+            .err()
+            .unwrap();
+        assert!(matches!(err, SceneError::Parse { .. }));
+        assert!(err.to_string().contains("line 2"), "{err}");
+
+        let source = r#"
+[textures.checker]
+type = "checker"
+even = [1, 1, 1]
+odd = "missing"
+"#;
+        let err = parse_scene(source, Path::new("test.toml")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.toml: textures.checker: unknown texture `missing`"
+        );
+
+        let source = "[background]\ntype = \"environment\"\npath = \"missing.hdr\"\n";
+        let err = parse_scene(source, Path::new("scenes/test.toml"))
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .starts_with("scenes/test.toml: background: scenes/missing.hdr: "),
+            "{err}"
+        );
+
+        let source = "[background]\ntype = \"sky\"\nsun_direction = [0, 0, 0]\n";
+        let err = parse_scene(source, Path::new("test.toml")).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "test.toml: background: `sun_direction` must not be zero"
+        );
+        let source = "[background]\ntype = \"sky\"\nsun_direction = [1, 1, 0]\n";
+        let scene = parse_scene(source, Path::new("test.toml")).unwrap();
+        assert!(matches!(scene.background, Background::Sky(_)));
+
+        for (source, message) in [
+            (
+                "[render]\nsamples_per_pixel = 0\n",
+                "`samples_per_pixel` must be at least 1, got 0",
+            ),
+            (
+                "[render]\nmax_depth = 0\n",
+                "`max_depth` must be at least 1, got 0",
+            ),
+        ] {
+            let err = parse_scene(source, Path::new("test.toml")).err().unwrap();
+            assert_eq!(err.to_string(), format!("test.toml: render: {message}"));
+        }
+    }
+}
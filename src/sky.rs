@@ -0,0 +1,261 @@
+//! An analytic daylight sky (Preetham, Shirley and Smits, "A Practical Analytic Model for
+//! Daylight", 1999) with the sun in it.
+//!
+//! The sky's brightness and color in each direction follow from the sun's position and
+//! the atmosphere's turbidity: 2 is a very clear sky, 3 a typical clear one and 10 a hazy
+//! one. The sun is a small disk of the same brightness it has in reality, reddened by
+//! the air it shines through, which integrators sample as a light. Below the horizon is
+//! a flat, diffuse ground lit by both.
+//!
+//! Radiance is in units of 50 kcd/m² (so a white surface under the midday sun comes out
+//! around 1), times `intensity`.
+
+use std::f64::consts::PI;
+
+use crate::sampling::{uniform_cone, uniform_cone_pdf, Onb};
+use crate::vec3::{dot, Color, Vec3, COLOR_BLACK};
+
+/// Angular radius of the sun, in radians.
+const SUN_RADIUS: f64 = 0.004_65;
+/// Illuminance of sunlight above the atmosphere, in klx.
+const SOLAR_ILLUMINANCE: f64 = 128.0;
+/// Sky luminances (in kcd/m²) per unit of radiance.
+const LUMINANCE_PER_UNIT: f64 = 50.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhysicalSky {
+    // towards the sun
+    sun: Vec3,
+    cos_sun_radius: f64,
+    sun_radiance: Color,
+    // the zenith's luminance and chromaticity, and each one's Perez coefficients
+    zenith: [f64; 3],
+    perez: [[f64; 5]; 3],
+    ground: Color,
+    intensity: f64,
+}
+
+impl PhysicalSky {
+    /// A sky with the sun towards `sun_direction` (which needn't be normalized) and the
+    /// given turbidity (clamped to `[1.7, 10]`, where the model holds), over ground that
+    /// reflects `ground_albedo` of the light falling on it.
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Color, intensity: f64) -> Self {
+        let sun = sun_direction.unit_vector();
+        let t = turbidity.clamp(1.7, 10.0);
+        // The model only covers suns above the horizon; lower ones light the sky as if
+        // they were setting.
+        let theta_s = sun.y().clamp(0.0, 1.0).acos();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic =
+            |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let linear = |c: [[f64; 2]; 5]| c.map(|[a, b]| a * t + b);
+        let perez = [
+            linear([
+                [0.1787, -1.4630],
+                [-0.3554, 0.4275],
+                [-0.0227, 5.3251],
+                [0.1206, -2.5771],
+                [-0.0670, 0.3703],
+            ]),
+            linear([
+                [-0.0193, -0.2592],
+                [-0.0665, 0.0008],
+                [-0.0004, 0.2125],
+                [-0.0641, -0.8989],
+                [-0.0033, 0.0452],
+            ]),
+            linear([
+                [-0.0167, -0.2608],
+                [-0.0950, 0.0092],
+                [-0.0079, 0.2102],
+                [-0.0441, -1.6537],
+                [-0.0109, 0.0529],
+            ]),
+        ];
+
+        let cos_sun_radius = SUN_RADIUS.cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        let sun_radiance = if sun.y() > 0.0 {
+            sun_transmittance(sun.y(), t) * (SOLAR_ILLUMINANCE / sun_solid_angle)
+        } else {
+            COLOR_BLACK
+        };
+
+        let mut sky = PhysicalSky {
+            sun,
+            cos_sun_radius,
+            sun_radiance,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            ground: COLOR_BLACK,
+            intensity,
+        };
+        sky.ground = ground_albedo * sky.horizontal_illuminance() / PI;
+        sky
+    }
+
+    // Sky luminance (kcd/m²) and chromaticity in direction `dir` above the horizon, as
+    // linear sRGB, without the sun.
+    fn sky_color(&self, dir: Vec3) -> Color {
+        // keeps the model finite right at the horizon
+        let cos_theta = dir.y().max(1e-3);
+        let theta_s = self.sun.y().clamp(0.0, 1.0).acos();
+        let gamma = dot(dir, self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = std::array::from_fn(|i| {
+            let perez = |cos_theta: f64, gamma: f64| {
+                let [a, b, c, d, e] = self.perez[i];
+                (1.0 + a * (b / cos_theta).exp())
+                    * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+            };
+            self.zenith[i] * perez(cos_theta, gamma) / perez(1.0, theta_s)
+        });
+        xyy_to_rgb(x, y, luminance.max(0.0))
+    }
+
+    // Light falling on the ground from the sky and the sun, in kcd/m² times steradians.
+    fn horizontal_illuminance(&self) -> Color {
+        let (rows, columns) = (32, 64);
+        let (d_theta, d_phi) = (PI / 2.0 / rows as f64, 2.0 * PI / columns as f64);
+        let mut total = COLOR_BLACK;
+        for row in 0..rows {
+            let theta = (row as f64 + 0.5) * d_theta;
+            for column in 0..columns {
+                let phi = (column as f64 + 0.5) * d_phi;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                total += self.sky_color(dir) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        let sun_solid_angle = 2.0 * PI * (1.0 - self.cos_sun_radius);
+        total + self.sun_radiance * (sun_solid_angle * self.sun.y().max(0.0))
+    }
+
+    /// Light arriving from direction `dir`.
+    pub fn color(&self, dir: Vec3) -> Color {
+        let dir = dir.unit_vector();
+        let luminance = if dir.y() < 0.0 {
+            self.ground
+        } else if dot(dir, self.sun) >= self.cos_sun_radius {
+            self.sky_color(dir) + self.sun_radiance
+        } else {
+            self.sky_color(dir)
+        };
+        luminance * (self.intensity / LUMINANCE_PER_UNIT)
+    }
+
+    /// Whether the sun is up, and so can be sampled.
+    pub fn sun_is_up(&self) -> bool {
+        self.sun_radiance != COLOR_BLACK
+    }
+
+    /// A direction towards the sun's disk, from a sample in `[0, 1)²`.
+    pub fn sample_sun(&self, sample: (f64, f64)) -> Vec3 {
+        Onb::from_w(self.sun).local(uniform_cone(sample, self.cos_sun_radius))
+    }
+
+    /// Density of `sample_sun` picking `dir`.
+    pub fn sun_pdf(&self, dir: Vec3) -> f64 {
+        if self.sun_is_up() && dot(dir.unit_vector(), self.sun) >= self.cos_sun_radius {
+            uniform_cone_pdf(self.cos_sun_radius)
+        } else {
+            0.0
+        }
+    }
+}
+
+// Fraction of sunlight that makes it through the atmosphere, per channel, when the sun's
+// cosine with the zenith is `cos_theta` (Preetham et al.'s appendix, without ozone and
+// water vapour).
+fn sun_transmittance(cos_theta: f64, turbidity: f64) -> Color {
+    let zenith_degrees = cos_theta.acos().to_degrees();
+    // relative optical air mass (Kasten and Young), which stays finite at the horizon
+    let air_mass = 1.0 / (cos_theta + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+    // Ångström's turbidity coefficient, with a wavelength exponent of 1.3
+    let beta = 0.04608 * turbidity - 0.04586;
+    // a representative wavelength (in μm) per channel
+    let channel = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Color::new(channel(0.68), channel(0.55), channel(0.44))
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return COLOR_BLACK;
+    }
+    let (cx, cy, cz) = (x * luminance / y, luminance, (1.0 - x - y) * luminance / y);
+    let rgb = Color::new(
+        3.2406 * cx - 1.5372 * cy - 0.4986 * cz,
+        -0.9689 * cx + 1.8758 * cy + 0.0415 * cz,
+        0.0557 * cx - 0.2040 * cy + 1.0570 * cz,
+    );
+    // keep the model's few out-of-gamut colors from going negative
+    Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::{luminance, COLOR_WHITE};
+
+    #[test]
+    fn test_sky_brightness() {
+        let sky = PhysicalSky::new(Vec3::new(0.0, 1.0, 1.0), 3.0, COLOR_WHITE * 0.3, 1.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        // the zenith has its given luminance
+        let zenith = luminance(sky.color(up)) * LUMINANCE_PER_UNIT;
+        assert!(
+            (zenith - sky.zenith[0]).abs() < 0.01 * sky.zenith[0],
+            "{zenith}"
+        );
+        // blue overhead, and brighter towards the sun than away from it
+        let overhead = sky.color(up);
+        assert!(overhead.z() > overhead.x());
+        let towards = sky.color(Vec3::new(0.0, 0.5, 1.0));
+        let away = sky.color(Vec3::new(0.0, 0.5, -1.0));
+        assert!(luminance(towards) > 2.0 * luminance(away));
+        // the same ground everywhere, lit mostly by the sun
+        let ground = sky.color(Vec3::new(0.3, -1.0, 0.2));
+        assert_eq!(ground, sky.color(Vec3::new(-1.0, -0.1, 0.0)));
+        assert!(
+            luminance(ground) > 0.1 && luminance(ground) < 1.0,
+            "{ground:?}"
+        );
+    }
+
+    #[test]
+    fn test_sun() {
+        let high = PhysicalSky::new(Vec3::new(0.0, 1.0, 0.3), 3.0, COLOR_WHITE, 1.0);
+        let low = PhysicalSky::new(Vec3::new(0.0, 0.1, 1.0), 3.0, COLOR_WHITE, 1.0);
+        // redder, and dimmer, low in the sky
+        let ratio = |sky: &PhysicalSky| sky.sun_radiance.x() / sky.sun_radiance.z();
+        assert!(ratio(&low) > 2.0 * ratio(&high));
+        assert!(luminance(low.sun_radiance) < luminance(high.sun_radiance));
+
+        for sample in [(0.0, 0.0), (0.5, 0.25), (0.99, 0.7)] {
+            let dir = high.sample_sun(sample);
+            assert!(dot(dir, high.sun) >= high.cos_sun_radius - 1e-12);
+            assert_eq!(high.sun_pdf(dir), uniform_cone_pdf(high.cos_sun_radius));
+            assert!(luminance(high.color(dir)) > 1000.0);
+        }
+        assert_eq!(high.sun_pdf(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+
+        let night = PhysicalSky::new(Vec3::new(0.0, -0.2, 1.0), 3.0, COLOR_WHITE, 1.0);
+        assert!(!night.sun_is_up());
+        assert_eq!(night.sun_pdf(Vec3::new(0.0, -0.2, 1.0)), 0.0);
+    }
+}
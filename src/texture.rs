@@ -0,0 +1,324 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::image_io::{load_image, Image};
+use crate::perlin::Perlin;
+use crate::vec3::{Color, Point3};
+
+/// A color that varies over a surface, looked up by surface coordinates `(u, v)` and/or
+/// by the hit point `p`.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+/// Alternates between two textures in a 3D grid of cubes `scale` wide, so it doesn't
+/// depend on the surface having sensible UVs.
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Self {
+        Self::new(
+            scale,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let x = (self.inv_scale * p.x()).floor() as i64;
+        let y = (self.inv_scale * p.y()).floor() as i64;
+        let z = (self.inv_scale * p.z()).floor() as i64;
+        if (x + y + z).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+/// What an image texture does with UVs outside `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    /// tile the image
+    #[default]
+    Repeat,
+    /// stretch the edge pixels outwards
+    Clamp,
+    /// tile the image, flipping every other copy
+    Mirror,
+}
+
+impl WrapMode {
+    fn wrap(self, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+/// An image mapped onto the surface's UVs, with `(0, 0)` at the bottom-left of the image.
+pub struct ImageTexture {
+    image: Image,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    /// Unless it's already `linear`, `image` is taken as sRGB-encoded, as image files
+    /// usually are, and linearized.
+    pub fn new(mut image: Image, wrap: WrapMode) -> Self {
+        if image.linear {
+            return Self { image, wrap };
+        }
+        for pixel in image.pixels.iter_mut() {
+            *pixel = Color::new(
+                srgb_to_linear(pixel.x()),
+                srgb_to_linear(pixel.y()),
+                srgb_to_linear(pixel.z()),
+            );
+        }
+        Self { image, wrap }
+    }
+
+    /// Loads a PNG, PPM, HDR or PFM file.
+    pub fn load(path: &Path, wrap: WrapMode) -> io::Result<Self> {
+        let image = load_image(path)?;
+        if image.width == 0 || image.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "texture image is empty",
+            ));
+        }
+        Ok(Self::new(image, wrap))
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        self.image.pixel(
+            self.wrap.wrap(x, self.image.width),
+            self.wrap.wrap(y, self.image.height),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    // Bilinear filtering between the four nearest pixel centers.
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        let x = u * self.image.width as f64 - 0.5;
+        let y = (1.0 - v) * self.image.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// Grayscale fractal (fBm) noise; `scale` is the frequency, so larger values give finer
+/// detail.
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+}
+
+impl NoiseTexture {
+    pub fn new(seed: u64, scale: f64, octaves: u32) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let n = self.noise.fbm(self.scale * p, self.octaves);
+        Color::new(1.0, 1.0, 1.0) * (0.5 * (1.0 + n))
+    }
+}
+
+/// Marble: bands along z, blended between two colors and bent by turbulence.
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+    base: Color,
+    vein: Color,
+}
+
+impl MarbleTexture {
+    pub fn new(seed: u64, scale: f64, octaves: u32, base: Color, vein: Color) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale,
+            octaves,
+            base,
+            vein,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let turbulence = self.noise.turbulence(p, self.octaves);
+        let t = 0.5 * (1.0 + (self.scale * p.z() + 10.0 * turbulence).sin());
+        lerp(self.vein, self.base, t)
+    }
+}
+
+/// Wood: concentric rings around the y axis, `scale` rings per unit, wobbled by
+/// turbulence and blended from `light` to `dark` across each ring.
+pub struct WoodTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: u32,
+    light: Color,
+    dark: Color,
+}
+
+impl WoodTexture {
+    pub fn new(seed: u64, scale: f64, octaves: u32, light: Color, dark: Color) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            scale,
+            octaves,
+            light,
+            dark,
+        }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let rings = self.scale * radius + 2.0 * self.noise.turbulence(p, self.octaves);
+        lerp(self.light, self.dark, rings.fract())
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    a * (1.0 - t) + b * t
+}
+
+/// Decodes an sRGB-encoded channel in `[0, 1]` to linear.
+pub fn srgb_to_linear(x: f64) -> f64 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::COLOR_BLACK;
+
+    #[test]
+    fn test_checker() {
+        let checker = CheckerTexture::from_colors(1.0, COLOR_BLACK, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(
+            checker.value(0.0, 0.0, Point3::new(0.5, 0.5, 0.5)),
+            COLOR_BLACK
+        );
+        assert_eq!(
+            checker.value(0.0, 0.0, Point3::new(-0.5, 0.5, 0.5)),
+            Color::new(1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            checker.value(0.0, 0.0, Point3::new(-0.5, -0.5, 0.5)),
+            COLOR_BLACK
+        );
+    }
+
+    #[test]
+    fn test_image_texture_filtering_and_wrapping() {
+        // 2x1: black on the left, white on the right
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![COLOR_BLACK, Color::new(1.0, 1.0, 1.0)],
+            linear: false,
+        };
+        let p = Point3::new(0.0, 0.0, 0.0);
+
+        let clamped = ImageTexture::new(image.clone(), WrapMode::Clamp);
+        assert_eq!(clamped.value(0.25, 0.5, p), COLOR_BLACK);
+        assert_eq!(clamped.value(0.5, 0.5, p), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(clamped.value(1.5, 0.5, p), Color::new(1.0, 1.0, 1.0));
+
+        let repeated = ImageTexture::new(image.clone(), WrapMode::Repeat);
+        // halfway between the right edge and the (wrapped) left edge
+        assert_eq!(repeated.value(1.0, 0.5, p), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(repeated.value(1.25, 0.5, p), COLOR_BLACK);
+
+        let mirrored = ImageTexture::new(image, WrapMode::Mirror);
+        assert_eq!(mirrored.value(1.25, 0.5, p), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_noise_textures_stay_between_their_colors() {
+        let (light, dark) = (Color::new(0.9, 0.7, 0.5), Color::new(0.3, 0.2, 0.1));
+        let textures: Vec<Box<dyn Texture>> = vec![
+            Box::new(NoiseTexture::new(3, 4.0, 7)),
+            Box::new(MarbleTexture::new(3, 4.0, 7, light, dark)),
+            Box::new(WoodTexture::new(3, 4.0, 7, light, dark)),
+        ];
+        for texture in &textures {
+            for i in 0..50 {
+                let p = Point3::new(i as f64 * 0.31, i as f64 * -0.17, i as f64 * 0.07);
+                let c = texture.value(0.0, 0.0, p);
+                for channel in [c.x(), c.y(), c.z()] {
+                    assert!((0.0..=1.0).contains(&channel), "{c:?} at {p:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,421 @@
+//! Turning rendered radiance into display colors.
+//!
+//! Radiance is scaled by the exposure, squeezed into the displayable range by a tone
+//! mapping operator, and encoded with the sRGB transfer function. Before it's quantized
+//! (e.g. to 8 bits), an optional dither spreads the rounding error out as fine noise
+//! instead of visible bands in smooth gradients.
+
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::util::stream_seed;
+use crate::vec3::{luminance, Color};
+
+/// How radiance is compressed into `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMap {
+    /// None: anything brighter than 1 is clipped.
+    #[default]
+    Clamp,
+    /// Reinhard's operator on luminance, `L / (1 + L)`, which never quite reaches white.
+    Reinhard,
+    /// Reinhard's operator extended so luminance `white_point` (and above) is white.
+    ExtendedReinhard,
+    /// A fit of the ACES filmic curve (the reference rendering and output transforms),
+    /// with its contrast and desaturated highlights.
+    Aces,
+    /// Troy Sobotka's AgX, which keeps very bright colors from skewing in hue.
+    Agx,
+}
+
+/// How display colors are dithered before they're quantized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    #[default]
+    None,
+    /// An 8×8 Bayer matrix: a regular, crosshatched pattern.
+    Ordered,
+    /// A tiled 64×64 blue noise mask: irregular, but without clumps.
+    BlueNoise,
+}
+
+/// Every setting of turning radiance into display colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops: radiance is scaled by `2^exposure`.
+    pub exposure: f64,
+    pub operator: ToneMap,
+    /// Luminance mapped to white by `ToneMap::ExtendedReinhard`.
+    pub white_point: f64,
+    pub dither: Dither,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            exposure: 0.0,
+            operator: ToneMap::default(),
+            white_point: 4.0,
+            dither: Dither::default(),
+        }
+    }
+}
+
+impl ToneMapping {
+    /// The sRGB-encoded display color, in `[0, 1]`, of linear `radiance`.
+    pub fn apply(&self, radiance: Color) -> Color {
+        let color = radiance * 2f64.powf(self.exposure);
+        let color = match self.operator {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => color / (1.0 + luminance(color).max(0.0)),
+            ToneMap::ExtendedReinhard => {
+                let l = luminance(color).max(0.0);
+                let white = self.white_point * self.white_point;
+                color * ((1.0 + l / white) / (1.0 + l))
+            }
+            ToneMap::Aces => aces(color),
+            ToneMap::Agx => agx(color),
+        };
+        let encode = |x: f64| linear_to_srgb(x.clamp(0.0, 1.0));
+        Color::new(encode(color.x()), encode(color.y()), encode(color.z()))
+    }
+
+    /// Offsets the display color of pixel `(x, y)` by up to half of a quantization `step`
+    /// (e.g. 1/255 for 8 bits) either way, following the dither pattern.
+    pub fn dither(&self, x: u32, y: u32, color: Color, step: f64) -> Color {
+        let threshold = match self.dither {
+            Dither::None => return color,
+            Dither::Ordered => bayer(x, y),
+            Dither::BlueNoise => {
+                let size = BLUE_NOISE_SIZE as u32;
+                blue_noise()[((y % size) * size + x % size) as usize]
+            }
+        };
+        let offset = (threshold - 0.5) * step;
+        color + Color::new(offset, offset, offset)
+    }
+}
+
+/// The sRGB transfer function: encodes a linear value in `[0, 1]` for display.
+pub fn linear_to_srgb(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn mat_mul(m: [[f64; 3]; 3], c: Color) -> Color {
+    let row = |r: [f64; 3]| r[0] * c.x() + r[1] * c.y() + r[2] * c.z();
+    Color::new(row(m[0]), row(m[1]), row(m[2]))
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT, in linear sRGB.
+fn aces(color: Color) -> Color {
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let fit = |v: f64| {
+        let v = v.max(0.0);
+        (v * (v + 0.024_578_6) - 0.000_090_537) / (v * (0.983_729 * v + 0.432_951) + 0.238_081)
+    };
+    let c = mat_mul(INPUT, color);
+    mat_mul(OUTPUT, Color::new(fit(c.x()), fit(c.y()), fit(c.z())))
+}
+
+// AgX's base look, after Benjamin Wrensch's polynomial fit, in linear sRGB.
+fn agx(color: Color) -> Color {
+    const INSET: [[f64; 3]; 3] = [
+        [
+            0.842_479_062_253_094,
+            0.078_433_599_999_999_2,
+            0.079_223_745_147_764_3,
+        ],
+        [
+            0.042_328_242_261_012_3,
+            0.878_468_636_469_772,
+            0.079_166_127_460_543_4,
+        ],
+        [0.042_375_654_905_705_1, 0.078_433_6, 0.879_142_973_793_104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [
+            1.196_879_005_120_17,
+            -0.098_020_881_140_136_8,
+            -0.099_029_744_079_720_5,
+        ],
+        [
+            -0.052_896_851_757_456_2,
+            1.151_903_129_904_17,
+            -0.098_961_176_844_843_3,
+        ],
+        [
+            -0.052_971_635_514_443_8,
+            -0.098_043_450_117_124_1,
+            1.151_073_672_641_16,
+        ],
+    ];
+    // the range of stops around middle gray the curve covers
+    const MIN_EV: f64 = -12.473_931_188;
+    const MAX_EV: f64 = 4.026_068_812;
+    let contrast = |v: f64| {
+        let x = ((v.max(1e-10).log2() - MIN_EV) / (MAX_EV - MIN_EV)).clamp(0.0, 1.0);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let c = mat_mul(INSET, color);
+    let c = mat_mul(
+        OUTSET,
+        Color::new(contrast(c.x()), contrast(c.y()), contrast(c.z())),
+    );
+    // The curve's output is encoded for a 2.2 gamma display; linearize it again.
+    let linearize = |v: f64| v.max(0.0).powf(2.2);
+    Color::new(linearize(c.x()), linearize(c.y()), linearize(c.z()))
+}
+
+// Threshold in (0, 1) of pixel `(x, y)` in an 8×8 Bayer matrix.
+fn bayer(x: u32, y: u32) -> f64 {
+    // Interleave the bits of `x ^ y` and `y`, least significant first.
+    let (x, y) = (x & 7, y & 7);
+    let mut rank = 0;
+    for bit in 0..3 {
+        rank = (rank << 2) | (((x ^ y) >> bit) & 1) << 1 | ((y >> bit) & 1);
+    }
+    (rank as f64 + 0.5) / 64.0
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+// Thresholds in (0, 1) of a blue noise mask, row by row, generated the first time
+// they're needed.
+fn blue_noise() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE))
+}
+
+// Ulichney's void-and-cluster method: rank the pixels of a `size`×`size` tile so that
+// the first `k` of them, for any `k`, are spread as evenly as possible.
+fn void_and_cluster(size: usize) -> Vec<f64> {
+    let n = size * size;
+    let mut pattern = Pattern::new(size);
+    // Start from a few random points (from a fixed seed, so the mask never changes)...
+    let mut i = 0;
+    while pattern.count < n / 10 {
+        let pixel = (stream_seed(0, i) % n as u64) as usize;
+        if !pattern.on[pixel] {
+            pattern.toggle(pixel);
+        }
+        i += 1;
+    }
+    // ...and even them out by moving the most crowded point to the emptiest spot, until
+    // that's where it already was.
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // Rank the starting points by taking the most crowded away first, then the rest by
+    // filling the emptiest spots first.
+    let mut rank = vec![0; n];
+    let mut removing = pattern.clone();
+    for r in (0..pattern.count).rev() {
+        let cluster = removing.tightest_cluster();
+        removing.toggle(cluster);
+        rank[cluster] = r;
+    }
+    for r in pattern.count..n {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        rank[void] = r;
+    }
+    rank.into_iter()
+        .map(|r| (r as f64 + 0.5) / n as f64)
+        .collect()
+}
+
+// A binary pattern on a torus, with each pixel's "energy": how close it is to the
+// pattern's points, through a Gaussian.
+#[derive(Clone)]
+struct Pattern {
+    size: usize,
+    on: Vec<bool>,
+    count: usize,
+    energy: Vec<f64>,
+    // the Gaussian by (wrapped) offset
+    kernel: Vec<f64>,
+}
+
+impl Pattern {
+    fn new(size: usize) -> Self {
+        let sigma = 1.5;
+        let kernel = (0..size * size)
+            .map(|i| {
+                let (dx, dy) = (i % size, i / size);
+                let (dx, dy) = (dx.min(size - dx) as f64, dy.min(size - dy) as f64);
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        Pattern {
+            size,
+            on: vec![false; size * size],
+            count: 0,
+            energy: vec![0.0; size * size],
+            kernel,
+        }
+    }
+
+    fn toggle(&mut self, pixel: usize) {
+        let sign = if self.on[pixel] { -1.0 } else { 1.0 };
+        self.on[pixel] = !self.on[pixel];
+        self.count = if sign > 0.0 {
+            self.count + 1
+        } else {
+            self.count - 1
+        };
+        let (px, py) = (pixel % self.size, pixel / self.size);
+        for (i, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (i % self.size + self.size - px) % self.size;
+            let dy = (i / self.size + self.size - py) % self.size;
+            *energy += sign * self.kernel[dy * self.size + dx];
+        }
+    }
+
+    // the point with the most energy
+    fn tightest_cluster(&self) -> usize {
+        (0..self.on.len())
+            .filter(|&i| self.on[i])
+            .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .expect("pattern has points")
+    }
+
+    // the empty pixel with the least energy
+    fn largest_void(&self) -> usize {
+        (0..self.on.len())
+            .filter(|&i| !self.on[i])
+            .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+            .expect("pattern has empty pixels")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::srgb_to_linear;
+
+    #[test]
+    fn test_srgb_transfer() {
+        for x in [0.0, 0.002, 0.003_130_8, 0.01, 0.18, 0.5, 1.0] {
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-9, "{x}");
+        }
+        assert!((linear_to_srgb(0.18) - 0.4614).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_operators() {
+        let gray = |y: f64| Color::new(y, y, y);
+        for operator in [
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ExtendedReinhard,
+            ToneMap::Aces,
+            ToneMap::Agx,
+        ] {
+            let mapping = ToneMapping {
+                operator,
+                ..ToneMapping::default()
+            };
+            let mut last = -1.0;
+            for y in [0.0, 0.01, 0.1, 0.18, 0.5, 1.0, 2.0, 10.0, 1000.0] {
+                let display = mapping.apply(gray(y));
+                assert!(
+                    (0.0..=1.0).contains(&display.x()),
+                    "{operator:?} {display:?}"
+                );
+                assert!(display.x() >= last, "{operator:?} isn't monotonic at {y}");
+                last = display.x();
+            }
+            assert!(mapping.apply(gray(1000.0)).x() > 0.9, "{operator:?}");
+            assert!(mapping.apply(gray(0.0)).x() < 0.05, "{operator:?}");
+        }
+
+        let mapping = |operator, exposure| ToneMapping {
+            operator,
+            exposure,
+            ..ToneMapping::default()
+        };
+        let half = mapping(ToneMap::Reinhard, 0.0).apply(gray(1.0));
+        assert!((half.x() - linear_to_srgb(0.5)).abs() < 1e-9);
+        assert_eq!(
+            mapping(ToneMap::Reinhard, 1.0).apply(gray(0.5)),
+            mapping(ToneMap::Reinhard, 0.0).apply(gray(1.0))
+        );
+        let white = mapping(ToneMap::ExtendedReinhard, 0.0).apply(gray(4.0));
+        assert!((white.x() - 1.0).abs() < 1e-9);
+        assert_eq!(
+            mapping(ToneMap::Clamp, 0.0).apply(gray(0.5)).x(),
+            linear_to_srgb(0.5)
+        );
+    }
+
+    #[test]
+    fn test_dither_patterns() {
+        let mut ranks: Vec<f64> = (0..64).map(|i| bayer(i % 8, i / 8)).collect();
+        ranks.sort_by(f64::total_cmp);
+        assert!(ranks
+            .iter()
+            .enumerate()
+            .all(|(i, &r)| r == (i as f64 + 0.5) / 64.0));
+        assert_eq!(bayer(8, 8), bayer(0, 0));
+
+        let mask = blue_noise();
+        let n = BLUE_NOISE_SIZE * BLUE_NOISE_SIZE;
+        let mut ranks: Vec<usize> = mask.iter().map(|t| (t * n as f64) as usize).collect();
+        ranks.sort();
+        assert!(ranks.iter().enumerate().all(|(i, &r)| r == i));
+        // Blue noise has little low-frequency content: 8×8 blocks average out much closer
+        // to 1/2 than with white noise, whose block means are off by 0.036 on average.
+        let blocks = n / 64;
+        let squared_error = (0..blocks)
+            .map(|block| {
+                let (bx, by) = (block % 8 * 8, block / 8 * 8);
+                let mean = (0..64)
+                    .map(|i| mask[(by + i / 8) * BLUE_NOISE_SIZE + bx + i % 8])
+                    .sum::<f64>()
+                    / 64.0;
+                (mean - 0.5).powi(2)
+            })
+            .sum::<f64>();
+        let rms = (squared_error / blocks as f64).sqrt();
+        assert!(rms < 0.015, "{rms}");
+
+        // Dithering a flat color between two levels rounds the right share of pixels up.
+        let mapping = ToneMapping {
+            dither: Dither::BlueNoise,
+            ..ToneMapping::default()
+        };
+        let level = Color::new(100.3, 100.3, 100.3) / 255.0;
+        let rounded_up = (0..64 * 64)
+            .filter(|&i| {
+                let dithered = mapping.dither(i % 64, i / 64, level, 1.0 / 255.0);
+                (dithered.x() * 255.0).round() > 100.0
+            })
+            .count();
+        assert!((rounded_up as f64 / 4096.0 - 0.3).abs() < 0.01);
+    }
+}
@@ -0,0 +1,597 @@
+use std::ops::Mul;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::sampler::Sampler;
+use crate::util::degrees_to_radians;
+use crate::vec3::{Point3, Vec3};
+use crate::{HitRecord, Hittable, Ray};
+
+/// A 4x4 matrix acting on column vectors, i.e. `m * p`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut t = [[0.0; 4]; 4];
+        for (i, row) in t.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Matrix4 { m: t }
+    }
+
+    /// Inverse by Gauss-Jordan elimination, or `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inv = Matrix4::IDENTITY.m;
+        for col in 0..4 {
+            // partial pivoting, for stability
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= scale;
+                inv[col][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[col][j];
+                        inv[row][j] -= factor * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        let x = m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3];
+        let y = m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3];
+        let z = m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3];
+        let w = m[3][0] * p.x() + m[3][1] * p.y() + m[3][2] * p.z() + m[3][3];
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x, y, z) / w
+        }
+    }
+
+    /// Determinant of the upper-left 3x3, i.e. how much the matrix scales volumes.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Transforms a direction, which (unlike a point) isn't affected by translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+/// An invertible affine transform, stored along with its inverse.
+///
+/// Transforms compose like matrices: `a * b` applies `b` first, then `a`, so
+/// `Transform::translate(t) * Transform::rotate_y(45.0) * Transform::scale(s)` scales,
+/// then rotates, then moves an object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        matrix: Matrix4::IDENTITY,
+        inverse: Matrix4::IDENTITY,
+    };
+
+    /// `None` if `matrix` can't be inverted (e.g. it scales something to zero).
+    pub fn new(matrix: Matrix4) -> Option<Transform> {
+        Some(Transform {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translate(offset: Vec3) -> Transform {
+        let (x, y, z) = (offset.x(), offset.y(), offset.z());
+        Transform {
+            matrix: Matrix4 {
+                m: [
+                    [1.0, 0.0, 0.0, x],
+                    [0.0, 1.0, 0.0, y],
+                    [0.0, 0.0, 1.0, z],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            },
+            inverse: Matrix4 {
+                m: [
+                    [1.0, 0.0, 0.0, -x],
+                    [0.0, 1.0, 0.0, -y],
+                    [0.0, 0.0, 1.0, -z],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            },
+        }
+    }
+
+    /// Scales by `factors` along each axis; panics if any of them is zero.
+    pub fn scale(factors: Vec3) -> Transform {
+        let (x, y, z) = (factors.x(), factors.y(), factors.z());
+        assert!(x != 0.0 && y != 0.0 && z != 0.0, "can't scale by zero");
+        let diagonal = |x: f64, y: f64, z: f64| Matrix4 {
+            m: [
+                [x, 0.0, 0.0, 0.0],
+                [0.0, y, 0.0, 0.0],
+                [0.0, 0.0, z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        };
+        Transform {
+            matrix: diagonal(x, y, z),
+            inverse: diagonal(1.0 / x, 1.0 / y, 1.0 / z),
+        }
+    }
+
+    /// Rotates `degrees` counter-clockwise (looking down the axis towards the origin)
+    /// about `axis`, which needn't be normalized.
+    pub fn rotate(axis: Vec3, degrees: f64) -> Transform {
+        let a = axis.unit_vector();
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let matrix = Matrix4 {
+            m: [
+                [
+                    t * x * x + cos,
+                    t * x * y - sin * z,
+                    t * x * z + sin * y,
+                    0.0,
+                ],
+                [
+                    t * x * y + sin * z,
+                    t * y * y + cos,
+                    t * y * z - sin * x,
+                    0.0,
+                ],
+                [
+                    t * x * z - sin * y,
+                    t * y * z + sin * x,
+                    t * z * z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        };
+        // rotations are orthogonal
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Transform {
+        Transform::rotate(Vec3::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Transform {
+        Transform::rotate(Vec3::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Transform {
+        Transform::rotate(Vec3::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Transforms a surface normal, which needs the inverse transpose to stay
+    /// perpendicular to the surface under non-uniform scaling. Not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    /// Box enclosing `bbox` once transformed.
+    pub fn bounding_box(&self, bbox: Aabb) -> Aabb {
+        let corner = |i: usize| {
+            let pick = |axis: usize| {
+                if i & (1 << axis) == 0 {
+                    bbox.min[axis]
+                } else {
+                    bbox.max[axis]
+                }
+            };
+            self.point(Point3::new(pick(0), pick(1), pick(2)))
+        };
+        (1..8).fold(Aabb::from_points(corner(0), corner(0)), |acc, i| {
+            acc.surrounding(Aabb::from_points(corner(i), corner(i)))
+        })
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+/// An object placed in the scene by a transform.
+///
+/// The object is shared, so the same geometry (a mesh, or a whole `HitList`) can be
+/// instanced many times with different transforms without being copied.
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    bbox: Option<Aabb>,
+}
+
+impl Transformed {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        let bbox = object
+            .bounding_box()
+            .map(|bbox| transform.bounding_box(bbox));
+        Transformed {
+            object,
+            transform,
+            bbox,
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_transformed(self.object.as_ref(), &self.transform, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        pdf_transformed(self.object.as_ref(), &self.transform, origin, dir, time)
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        direction_transformed(self.object.as_ref(), &self.transform, origin, time, sampler)
+    }
+}
+
+/// An object that moves from one transform at `time0` to another at `time1` (and stays
+/// put before and after), for motion blur.
+///
+/// The matrices are blended element by element, which is exact for translation and
+/// scaling but only approximates rotation; that's fine for the small turns made while
+/// the shutter is open.
+pub struct Moving {
+    object: Arc<dyn Hittable>,
+    start: (Transform, f64),
+    end: (Transform, f64),
+    bbox: Option<Aabb>,
+}
+
+impl Moving {
+    pub fn new(
+        object: Arc<dyn Hittable>,
+        (start, time0): (Transform, f64),
+        (end, time1): (Transform, f64),
+    ) -> Self {
+        // Each point moves in a straight line between its start and end positions, so
+        // the boxes at either end cover the whole motion.
+        let bbox = object
+            .bounding_box()
+            .map(|bbox| start.bounding_box(bbox).surrounding(end.bounding_box(bbox)));
+        Moving {
+            object,
+            start: (start, time0),
+            end: (end, time1),
+            bbox,
+        }
+    }
+
+    /// The transform at `time`, or `None` if it happens to be singular.
+    pub fn transform_at(&self, time: f64) -> Option<Transform> {
+        let ((start, time0), (end, time1)) = (self.start, self.end);
+        if time1 == time0 {
+            return Some(start);
+        }
+        let s = crate::clamp((time - time0) / (time1 - time0), 0.0, 1.0);
+        let mut m = start.matrix;
+        for (row, end_row) in m.m.iter_mut().zip(end.matrix.m) {
+            for (x, end_x) in row.iter_mut().zip(end_row) {
+                *x += (end_x - *x) * s;
+            }
+        }
+        Transform::new(m)
+    }
+}
+
+impl Hittable for Moving {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let transform = self.transform_at(r.time)?;
+        hit_transformed(self.object.as_ref(), &transform, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, time: f64) -> f64 {
+        match self.transform_at(time) {
+            Some(transform) => pdf_transformed(self.object.as_ref(), &transform, origin, dir, time),
+            None => 0.0,
+        }
+    }
+
+    fn random_direction(&self, origin: Point3, time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        match self.transform_at(time) {
+            Some(transform) => {
+                direction_transformed(self.object.as_ref(), &transform, origin, time, sampler)
+            }
+            None => Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+}
+
+fn hit_transformed(
+    object: &dyn Hittable,
+    transform: &Transform,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord> {
+    // The direction isn't renormalized, so `t` means the same in both spaces.
+    let inverse = transform.inverse();
+    let object_ray = Ray::with_time(inverse.point(r.orig), inverse.vector(r.dir), r.time);
+    let rec = object.hit(&object_ray, t_min, t_max)?;
+    // `rec.normal` already faces against the ray, and still does once transformed.
+    Some(HitRecord {
+        p: transform.point(rec.p),
+        normal: transform.normal(rec.normal).unit_vector(),
+        ..rec
+    })
+}
+
+fn pdf_transformed(
+    object: &dyn Hittable,
+    transform: &Transform,
+    origin: Point3,
+    dir: Vec3,
+    time: f64,
+) -> f64 {
+    // A linear map squeezes and stretches solid angle too: a unit direction `w` maps to
+    // `B w` (normalized) with Jacobian `|det B| / |B w|^3`, `B` being the inverse.
+    let inverse = transform.inverse();
+    let w = dir.unit_vector();
+    let object_dir = inverse.vector(w);
+    let object_pdf = object.pdf_value(inverse.point(origin), object_dir, time);
+    if object_pdf == 0.0 {
+        return 0.0;
+    }
+    object_pdf * inverse.matrix().determinant3().abs() / object_dir.length().powi(3)
+}
+
+fn direction_transformed(
+    object: &dyn Hittable,
+    transform: &Transform,
+    origin: Point3,
+    time: f64,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let object_origin = transform.inverse().point(origin);
+    let object_dir = object.random_direction(object_origin, time, sampler);
+    transform.vector(object_dir).unit_vector()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material};
+    use crate::quad::{Cuboid, Quad};
+    use crate::sampler::IndependentSampler;
+    use crate::vec3::Color;
+    use crate::Sphere;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn test_transform_composition_and_inverse() {
+        let t = Transform::translate(Vec3::new(1.0, 2.0, 3.0))
+            * Transform::rotate_y(90.0)
+            * Transform::scale(Vec3::new(2.0, 2.0, 2.0));
+        let p = Point3::new(1.0, 0.0, 0.0);
+        // scaled to (2, 0, 0), rotated to (0, 0, -2), moved
+        assert_near(t.point(p), Point3::new(1.0, 2.0, 1.0));
+        assert_near(t.inverse().point(t.point(p)), p);
+        assert_near(
+            t.vector(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -2.0),
+        );
+
+        let inverse = Transform::new(*t.matrix()).unwrap().inverse();
+        assert_near(inverse.point(Point3::new(1.0, 2.0, 1.0)), p);
+        assert!(Transform::new(Matrix4 { m: [[0.0; 4]; 4] }).is_none());
+    }
+
+    #[test]
+    fn test_transformed_sphere() {
+        // a unit sphere stretched into an ellipsoid 3 wide along x, then moved
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ));
+        let transform = Transform::translate(Vec3::new(0.0, 0.0, -10.0))
+            * Transform::scale(Vec3::new(3.0, 1.0, 1.0));
+        let instance = Transformed::new(sphere, transform);
+
+        let bbox = instance.bounding_box().unwrap();
+        assert_near(bbox.min, Point3::new(-3.0, -1.0, -11.0));
+        assert_near(bbox.max, Point3::new(3.0, 1.0, -9.0));
+
+        let r = Ray::new(Point3::new(10.0, 0.0, -10.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 7.0).abs() < 1e-9);
+        assert_near(rec.p, Point3::new(3.0, 0.0, -10.0));
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        // the stretched normal stays perpendicular to the surface
+        let r = Ray::new(Point3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let object_normal = Vec3::new(0.5, 0.0, 0.75f64.sqrt());
+        let expected = Vec3::new(object_normal.x() / 3.0, 0.0, object_normal.z()).unit_vector();
+        assert_near(rec.normal, expected);
+    }
+
+    #[test]
+    fn test_moving_object() {
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ));
+        let moving = Moving::new(
+            sphere,
+            (Transform::IDENTITY, 0.0),
+            (Transform::translate(Vec3::new(4.0, 0.0, 0.0)), 1.0),
+        );
+        let bbox = moving.bounding_box().unwrap();
+        assert_near(bbox.min, Point3::new(-1.0, -1.0, -1.0));
+        assert_near(bbox.max, Point3::new(5.0, 1.0, 1.0));
+
+        // halfway through, the sphere is centered on x = 2
+        let origin = Point3::new(2.0, 0.0, 5.0);
+        let dir = Vec3::new(0.0, 0.0, -1.0);
+        let rec = moving
+            .hit(&Ray::with_time(origin, dir, 0.5), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert!(moving
+            .hit(&Ray::with_time(origin, dir, 0.0), 0.001, f64::INFINITY)
+            .is_none());
+        // and it stops at the end
+        assert!(moving
+            .hit(&Ray::with_time(origin, dir, 2.0), 0.001, f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn test_transformed_light_sampling() {
+        // a stretched and turned quad samples like the same quad built in place
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let (q, u, v) = (
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let transform = Transform::translate(Vec3::new(1.0, 5.0, -2.0))
+            * Transform::rotate_x(30.0)
+            * Transform::scale(Vec3::new(2.0, 1.0, 3.0));
+        let instance = Transformed::new(Arc::new(Quad::new(q, u, v, Arc::clone(&mat))), transform);
+        let in_place = Quad::new(
+            transform.point(q),
+            transform.vector(u),
+            transform.vector(v),
+            mat,
+        );
+
+        let origin = Point3::new(0.5, -1.0, 0.5);
+        let mut sampler = IndependentSampler;
+        for _ in 0..10 {
+            let dir = instance.random_direction(origin, 0.0, &mut sampler);
+            assert!((dir.length() - 1.0).abs() < 1e-9);
+            let pdf = instance.pdf_value(origin, dir, 0.0);
+            assert!(pdf > 0.0);
+            assert!((pdf - in_place.pdf_value(origin, dir, 0.0)).abs() < 1e-9 * pdf);
+        }
+        assert_eq!(
+            instance.pdf_value(origin, Vec3::new(0.0, -1.0, 0.0), 0.0),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_moving_light_sampling() {
+        let cube: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ));
+        let moving = Moving::new(
+            cube,
+            (Transform::IDENTITY, 0.0),
+            (Transform::translate(Vec3::new(4.0, 0.0, 0.0)), 1.0),
+        );
+        // the cube is straight ahead only at the end of the shutter
+        let origin = Point3::new(4.0, 0.0, 10.0);
+        let dir = moving.random_direction(origin, 1.0, &mut IndependentSampler);
+        assert!(moving.pdf_value(origin, dir, 1.0) > 0.0);
+        let ahead = Vec3::new(0.0, 0.0, -1.0);
+        assert!(moving.pdf_value(origin, ahead, 1.0) > 0.0);
+        assert_eq!(moving.pdf_value(origin, ahead, 0.0), 0.0);
+    }
+}
@@ -0,0 +1,403 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::quad::area_pdf;
+use crate::sampler::Sampler;
+use crate::sampling::uniform_triangle;
+use crate::vec3::{dot, Point3, Vec3};
+use crate::{HitList, HitRecord, Hittable, Ray, INF};
+
+/// Where a ray crossed a triangle: its distance along the ray and the barycentric
+/// coordinates of the hit point, weighted as `(1 - u - v) * v0 + u * v1 + v * v2`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    pub t: f64,
+    pub u: f64,
+    pub v: f64,
+}
+
+impl TriangleHit {
+    /// Weight of the first vertex.
+    pub fn w(&self) -> f64 {
+        1.0 - self.u - self.v
+    }
+
+    /// Blends per-vertex values by the barycentric weights.
+    pub fn interpolate(&self, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+        a * self.w() + b * self.u + c * self.v
+    }
+}
+
+/// Möller–Trumbore ray/triangle intersection.
+pub fn intersect_triangle(
+    r: &Ray,
+    v0: Point3,
+    v1: Point3,
+    v2: Point3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<TriangleHit> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let pvec = r.dir.cross(edge2);
+    let det = dot(edge1, pvec);
+    // ray is parallel to the triangle's plane
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.orig - v0;
+    let u = dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let v = dot(r.dir, qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = dot(edge2, qvec) * inv_det;
+    if t < t_min || t_max < t {
+        return None;
+    }
+
+    Some(TriangleHit { t, u, v })
+}
+
+fn triangle_bounding_box(v0: Point3, v1: Point3, v2: Point3) -> Aabb {
+    Aabb::from_points(v0, v1).surrounding(Aabb::from_points(v2, v2))
+}
+
+// Density of `triangle_direction` picking `dir`: zero unless it hits the triangle.
+fn triangle_pdf(v0: Point3, v1: Point3, v2: Point3, origin: Point3, dir: Vec3) -> f64 {
+    let n = (v1 - v0).cross(v2 - v0);
+    match intersect_triangle(&Ray::new(origin, dir), v0, v1, v2, 0.001, INF) {
+        Some(hit) => area_pdf(dir * hit.t, n.unit_vector(), 0.5 * n.length()),
+        None => 0.0,
+    }
+}
+
+// Towards a point picked uniformly by area on the triangle.
+fn triangle_direction(
+    v0: Point3,
+    v1: Point3,
+    v2: Point3,
+    origin: Point3,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let (b1, b2) = uniform_triangle(sampler.get_2d());
+    let p = v0 * (1.0 - b1 - b2) + v1 * b1 + v2 * b2;
+    (p - origin).unit_vector()
+}
+
+/// A standalone, flat-shaded triangle.
+pub struct Triangle {
+    pub v0: Point3,
+    pub v1: Point3,
+    pub v2: Point3,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, mat_ptr: Arc<dyn Material>) -> Self {
+        Triangle {
+            v0,
+            v1,
+            v2,
+            mat_ptr,
+        }
+    }
+
+    pub fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<TriangleHit> {
+        intersect_triangle(r, self.v0, self.v1, self.v2, t_min, t_max)
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let hit = self.intersect(r, t_min, t_max)?;
+        let outward_normal = (self.v1 - self.v0).cross(self.v2 - self.v0).unit_vector();
+        let hr = HitRecord {
+            p: r.at(hit.t),
+            normal: outward_normal,
+            t: hit.t,
+            u: hit.u,
+            v: hit.v,
+            front_face: false,
+            mat_ptr: Arc::clone(&self.mat_ptr),
+            object_id: 0,
+            material_id: self.mat_ptr.id(),
+        };
+        Some(hr.with_face_normal(r, outward_normal))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_bounding_box(self.v0, self.v1, self.v2))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, _time: f64) -> f64 {
+        triangle_pdf(self.v0, self.v1, self.v2, origin, dir)
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        triangle_direction(self.v0, self.v1, self.v2, origin, sampler)
+    }
+}
+
+/// Indices of one mesh face into the vertex buffers of its `TriangleMesh`.
+///
+/// Positions, normals and UVs are indexed separately (as in OBJ files), so a vertex can
+/// share its position with neighbours while having a different normal or UV.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+}
+
+impl MeshFace {
+    pub fn new(positions: [usize; 3]) -> Self {
+        MeshFace {
+            positions,
+            normals: None,
+            uvs: None,
+        }
+    }
+}
+
+/// Triangles sharing vertex, normal and UV buffers and a single material.
+///
+/// Faces with vertex normals are smooth-shaded by interpolating them across the face.
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<MeshFace>,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Point3>, faces: Vec<MeshFace>, mat_ptr: Arc<dyn Material>) -> Self {
+        TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            faces,
+            mat_ptr,
+        }
+    }
+
+    /// One hittable per face, all pointing back at this mesh. Put the result in a
+    /// `BvhNode` rather than rendering it as a list.
+    pub fn triangles(self: &Arc<Self>) -> HitList {
+        let mut list = HitList::new();
+        for face in 0..self.faces.len() {
+            list.add(Box::new(MeshTriangle {
+                mesh: Arc::clone(self),
+                face,
+            }));
+        }
+        list
+    }
+
+    fn vertices(&self, face: &MeshFace) -> (Point3, Point3, Point3) {
+        let [a, b, c] = face.positions;
+        (self.positions[a], self.positions[b], self.positions[c])
+    }
+}
+
+/// A single face of a `TriangleMesh`.
+pub struct MeshTriangle {
+    mesh: Arc<TriangleMesh>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let face = &self.mesh.faces[self.face];
+        let (v0, v1, v2) = self.mesh.vertices(face);
+        let hit = intersect_triangle(r, v0, v1, v2, t_min, t_max)?;
+
+        let geometric_normal = (v1 - v0).cross(v2 - v0).unit_vector();
+        let shading_normal = face.normals.map(|[a, b, c]| {
+            let n = &self.mesh.normals;
+            hit.interpolate(n[a], n[b], n[c]).unit_vector()
+        });
+
+        // Without texture coordinates, fall back to the barycentric ones.
+        let (u, v) = match face.uvs {
+            Some([a, b, c]) => {
+                let uvs = &self.mesh.uvs;
+                let (w0, w1, w2) = (hit.w(), hit.u, hit.v);
+                (
+                    uvs[a].0 * w0 + uvs[b].0 * w1 + uvs[c].0 * w2,
+                    uvs[a].1 * w0 + uvs[b].1 * w1 + uvs[c].1 * w2,
+                )
+            }
+            None => (hit.u, hit.v),
+        };
+
+        let hr = HitRecord {
+            p: r.at(hit.t),
+            normal: geometric_normal,
+            t: hit.t,
+            u,
+            v,
+            front_face: false,
+            mat_ptr: Arc::clone(&self.mesh.mat_ptr),
+            object_id: 0,
+            material_id: self.mesh.mat_ptr.id(),
+        };
+
+        match shading_normal {
+            None => Some(hr.with_face_normal(r, geometric_normal)),
+            Some(shading_normal) => {
+                // Trust the vertex normals over the winding order for which side is "out".
+                let outward_normal = if dot(geometric_normal, shading_normal) < 0.0 {
+                    -geometric_normal
+                } else {
+                    geometric_normal
+                };
+                let mut rec = hr.with_face_normal(r, outward_normal);
+                rec.normal = if rec.front_face {
+                    shading_normal
+                } else {
+                    -shading_normal
+                };
+                Some(rec)
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (v0, v1, v2) = self.mesh.vertices(&self.mesh.faces[self.face]);
+        Some(triangle_bounding_box(v0, v1, v2))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3, _time: f64) -> f64 {
+        let (v0, v1, v2) = self.mesh.vertices(&self.mesh.faces[self.face]);
+        triangle_pdf(v0, v1, v2, origin, dir)
+    }
+
+    fn random_direction(&self, origin: Point3, _time: f64, sampler: &mut dyn Sampler) -> Vec3 {
+        let (v0, v1, v2) = self.mesh.vertices(&self.mesh.faces[self.face]);
+        triangle_direction(v0, v1, v2, origin, sampler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sampler::IndependentSampler;
+    use crate::vec3::Color;
+
+    #[test]
+    fn test_intersect_triangle_barycentrics() {
+        let r = Ray::new(Point3::new(0.25, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = intersect_triangle(
+            &r,
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            0.0,
+            f64::INFINITY,
+        );
+        assert_eq!(
+            hit,
+            Some(TriangleHit {
+                t: 1.0,
+                u: 0.25,
+                v: 0.5
+            })
+        );
+
+        let miss = Ray::new(Point3::new(0.75, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(
+            intersect_triangle(
+                &miss,
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+                0.0,
+                f64::INFINITY,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_triangle_sampling() {
+        let triangle = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(100.0, 0.0, 0.0),
+            Point3::new(0.0, 100.0, 0.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let origin = Point3::new(10.0, 10.0, 500.0);
+        let mut sampler = IndependentSampler;
+        for _ in 0..10 {
+            let dir = triangle.random_direction(origin, 0.0, &mut sampler);
+            assert!((dir.length() - 1.0).abs() < 1e-12);
+            assert!(triangle.pdf_value(origin, dir, 0.0) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_mesh_triangle_sampling_matches_triangle() {
+        let positions = vec![
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(100.0, 0.0, 0.0),
+            Point3::new(0.0, 100.0, 0.0),
+        ];
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let triangle = Triangle::new(positions[0], positions[1], positions[2], mat.clone());
+        let mesh = Arc::new(TriangleMesh::new(
+            positions,
+            vec![MeshFace::new([0, 1, 2])],
+            mat,
+        ));
+        let faces = mesh.triangles();
+        let origin = Point3::new(10.0, 10.0, 500.0);
+        for _ in 0..10 {
+            let dir = faces.random_direction(origin, 0.0, &mut IndependentSampler);
+            let pdf = faces.pdf_value(origin, dir, 0.0);
+            assert!(pdf > 0.0);
+            assert!((pdf - triangle.pdf_value(origin, dir, 0.0)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_mesh_interpolates_vertex_normals() {
+        let mut mesh = TriangleMesh::new(
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            vec![MeshFace {
+                positions: [0, 1, 2],
+                normals: Some([0, 1, 2]),
+                uvs: None,
+            }],
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        mesh.normals = vec![
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0).unit_vector(),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        let triangles = Arc::new(mesh).triangles();
+
+        let r = Ray::new(Point3::new(0.5, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = triangles.hit(&r, 0.0, f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+        let expected =
+            (Vec3::new(0.0, 0.0, 0.5) + Vec3::new(1.0, 0.0, 1.0).unit_vector() * 0.5).unit_vector();
+        assert!((rec.normal - expected).near_zero());
+    }
+}
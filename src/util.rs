@@ -1,16 +1,46 @@
+use std::cell::RefCell;
 use std::f64::consts::PI;
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 // Utility
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees / 180.0 * PI
 }
 
+thread_local! {
+    // Every random number in the renderer comes from here, so that reseeding it (see
+    // `seed_rng`) makes scene generation and rendering reproducible.
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::seed_from_u64(0));
+}
+
+/// Restarts the current thread's random number stream from `seed`.
+///
+/// The renderer reseeds once per pixel (with `stream_seed`), so a pixel's samples don't
+/// depend on which thread rendered it or what that thread rendered before.
+pub fn seed_rng(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Derives an independent seed for stream number `stream` (e.g. a pixel index) from a
+/// base seed, by mixing both through SplitMix64.
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
 pub fn random_double() -> f64 {
-    rand::thread_rng().gen::<f64>()
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
 }
 
 pub fn random_bounded(min: f64, max: f64) -> f64 {
-    rand::thread_rng().gen_range(min..max)
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..max))
+}
+
+/// Two random numbers in `[0, 1)`, the input the warps in `sampling` take.
+pub fn random_2d() -> (f64, f64) {
+    (random_double(), random_double())
 }
@@ -2,25 +2,38 @@ use std::ops::{
     Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign,
 };
 
-use rand::Rng;
+use serde::Deserialize;
 
-use crate::util::random_bounded;
+use crate::util::{random_bounded, random_double};
 
 pub fn dot(u: Vec3, v: Vec3) -> f64 {
     u[0] * v[0] + u[1] * v[1] + u[2] * v[2]
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Scene files write vectors as `[x, y, z]`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "[f64; 3]")]
 pub struct Vec3 {
     e: [f64; 3],
 }
 
+impl From<[f64; 3]> for Vec3 {
+    fn from(e: [f64; 3]) -> Vec3 {
+        Vec3 { e }
+    }
+}
+
 pub type Point3 = Vec3;
 pub type Color = Vec3;
 
 pub const COLOR_BLACK: Color = Color { e: [0.0, 0.0, 0.0] };
 pub const COLOR_WHITE: Color = Color { e: [1.0, 1.0, 1.0] };
 
+/// Relative brightness of a linear RGB color (Rec. 709 weights).
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
 impl Vec3 {
     pub fn new(e0: f64, e1: f64, e2: f64) -> Vec3 {
         Vec3 { e: [e0, e1, e2] }
@@ -46,6 +59,10 @@ impl Vec3 {
         self.dot(self).sqrt()
     }
 
+    pub fn max_component(self) -> f64 {
+        self[0].max(self[1]).max(self[2])
+    }
+
     pub fn cross(self, other: Vec3) -> Vec3 {
         Vec3 {
             e: [
@@ -66,8 +83,7 @@ impl Vec3 {
     }
 
     pub fn new_random() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        Vec3::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>())
+        Vec3::new(random_double(), random_double(), random_double())
     }
 
     pub fn new_random_bounded(min: f64, max: f64) -> Vec3 {
@@ -80,7 +96,7 @@ impl Vec3 {
 
     pub fn new_random_in_unit_sphere() -> Vec3 {
         loop {
-            let v = Self::new_random();
+            let v = Self::new_random_bounded(-1.0, 1.0);
             if dot(v, v) < 1.0 {
                 return v;
             }
@@ -139,7 +155,7 @@ impl Add for Vec3 {
 }
 
 impl AddAssign for Vec3 {
-    fn add_assign(&mut self, other: Vec3) -> () {
+    fn add_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] + other[0], self[1] + other[1], self[2] + other[2]],
         };
@@ -157,7 +173,7 @@ impl Sub for Vec3 {
 }
 
 impl SubAssign for Vec3 {
-    fn sub_assign(&mut self, other: Vec3) -> () {
+    fn sub_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] - other[0], self[1] - other[1], self[2] - other[2]],
         };
@@ -175,7 +191,7 @@ impl Mul<f64> for Vec3 {
 }
 
 impl MulAssign<f64> for Vec3 {
-    fn mul_assign(&mut self, other: f64) -> () {
+    fn mul_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] * other, self[1] * other, self[2] * other],
         };
@@ -213,7 +229,7 @@ impl Div<f64> for Vec3 {
 }
 
 impl DivAssign<f64> for Vec3 {
-    fn div_assign(&mut self, other: f64) -> () {
+    fn div_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] / other, self[1] / other, self[2] / other],
         };